@@ -5,12 +5,8 @@
 //!     2. This is the first launch of that major release
 
 use crate::terminal::print_separator;
-#[cfg(windows)]
-use crate::WINDOWS_DIRS;
-#[cfg(unix)]
-use crate::XDG_DIRS;
+use crate::utils::data_dir;
 use color_eyre::eyre::Result;
-use etcetera::base_strategy::BaseStrategy;
 use rust_i18n::t;
 use std::{
     env::var,
@@ -71,15 +67,6 @@ impl Version {
 /// We store them in the compiled binary.
 pub(crate) static BREAKINGCHANGES: &str = include_str!("../BREAKINGCHANGES.md");
 
-/// Return platform's data directory.
-fn data_dir() -> PathBuf {
-    #[cfg(unix)]
-    return XDG_DIRS.data_dir();
-
-    #[cfg(windows)]
-    return WINDOWS_DIRS.data_dir();
-}
-
 /// Return Topgrade's keep file path.
 ///
 /// keep file is a file under the data directory containing a major version
@@ -1,17 +1,358 @@
 //! Utilities for running commands and providing user-friendly error messages.
 
+use std::cell::{Cell, RefCell};
 use std::fmt::Display;
+use std::io::{Read, Write};
 use std::process::Child;
-use std::process::{Command, ExitStatus, Output};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{io, thread};
 
 use color_eyre::eyre;
 use color_eyre::eyre::eyre;
 use color_eyre::eyre::Context;
 
-use crate::error::TopgradeError;
+use crate::error::{StepTimedOut, TopgradeError};
+use crate::log_file;
+use crate::terminal::{is_capturing, is_unattended, write_captured};
 
 use tracing::debug;
 
+thread_local! {
+    /// When the commands of the step running on this thread have to be done by.
+    static DEADLINE: Cell<Option<Deadline>> = const { Cell::new(None) };
+
+    /// Whether a command of the step running on this thread was killed at the deadline.
+    static TIMED_OUT: Cell<bool> = const { Cell::new(false) };
+
+    /// The standard error of the commands run by the step on this thread, if it is being recorded.
+    static STDERR: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+
+    /// Whether the commands of the step running on this thread keep the terminal.
+    static ON_TERMINAL: Cell<bool> = const { Cell::new(false) };
+}
+
+/// When the commands of a step have to be done by.
+#[derive(Clone, Copy, Debug)]
+struct Deadline {
+    at: Instant,
+    /// The timeout of the step, which the deadline comes from.
+    timeout: Duration,
+    /// Whether the commands can run in the background. Commands that may prompt the user, e.g.
+    /// through `sudo`, have to be in the foreground to read from the terminal.
+    detach: bool,
+}
+
+/// How often to check whether a command with a deadline has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a command gets to exit after being asked to terminate, before it is killed.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Run `f`, killing the commands it runs once `timeout` has elapsed. Also tells whether a command
+/// was killed, even if `f` ignored the resulting error. The commands are only run in the
+/// background, with their input closed, if `detach` is set.
+pub fn with_timeout<T>(timeout: Option<Duration>, detach: bool, f: impl FnOnce() -> T) -> (T, bool) {
+    let Some(timeout) = timeout else {
+        return (f(), false);
+    };
+
+    DEADLINE.set(Some(Deadline {
+        at: Instant::now() + timeout,
+        timeout,
+        detach,
+    }));
+    TIMED_OUT.set(false);
+    let result = f();
+    DEADLINE.set(None);
+    (result, TIMED_OUT.replace(false))
+}
+
+/// Run `f`, leaving the commands it runs on the terminal if `keep` is set, e.g. so that they can
+/// prompt the user or show progress bars. Only their command lines go to the log file then, not
+/// their output.
+pub fn keep_terminal<T>(keep: bool, f: impl FnOnce() -> T) -> T {
+    ON_TERMINAL.set(keep);
+    let result = f();
+    ON_TERMINAL.set(false);
+    result
+}
+
+/// Whether to copy the output of the commands to the log file.
+fn is_logging() -> bool {
+    log_file::is_enabled() && !ON_TERMINAL.get()
+}
+
+/// Run `f`, also returning what the commands it runs wrote to their standard error if `enabled`
+/// is set. Their output still goes wherever it would go otherwise.
+pub fn record_stderr<T>(enabled: bool, f: impl FnOnce() -> T) -> (T, String) {
+    if !enabled {
+        return (f(), String::new());
+    }
+
+    STDERR.set(Some(Vec::new()));
+    let result = f();
+    let stderr = STDERR.take().unwrap_or_default();
+    (result, String::from_utf8_lossy(&stderr).into_owned())
+}
+
+fn is_recording_stderr() -> bool {
+    STDERR.with_borrow(Option::is_some)
+}
+
+fn record(stderr: &[u8]) {
+    STDERR.with_borrow_mut(|recorded| {
+        if let Some(recorded) = recorded {
+            recorded.extend_from_slice(stderr);
+        }
+    });
+}
+
+/// Like [`Command::output`], but kills the command at the deadline of the current step.
+pub fn output(cmd: &mut Command) -> eyre::Result<Output> {
+    let command = format_program_and_args(cmd);
+    let output = match DEADLINE.get() {
+        Some(deadline) => run_until(cmd, deadline, true, &command)?,
+        // This is where we implement `output` with a deadline, so we allow `Command::output` here.
+        #[allow(clippy::disallowed_methods)]
+        None => cmd.output().with_context(|| format!("Failed to execute `{command}`"))?,
+    };
+    record(&output.stderr);
+    Ok(output)
+}
+
+/// Run `cmd`, killing it if it is still running at `deadline`. The output is collected if
+/// `capture` is set, and goes to the terminal (and the log file) otherwise, with only a copy of the
+/// standard error returned.
+///
+/// The command runs in a process group of its own, which is killed along with everything the
+/// command started. A detached command runs in the background: it cannot read from the terminal,
+/// so its input is closed. Other commands get the terminal while they run, so that they can prompt
+/// the user.
+fn run_until(cmd: &mut Command, deadline: Deadline, capture: bool, command: &str) -> eyre::Result<Output> {
+    if Instant::now() >= deadline.at {
+        TIMED_OUT.set(true);
+        return Err(StepTimedOut(deadline.timeout).into());
+    }
+
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(cmd, 0);
+    if deadline.detach {
+        cmd.stdin(Stdio::null());
+    }
+    if capture || is_logging() || is_recording_stderr() {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    // This is where we implement running commands with a deadline, so we allow `Command::spawn`
+    // here.
+    #[allow(clippy::disallowed_methods)]
+    let mut child = cmd.spawn().with_context(|| format!("Failed to execute `{command}`"))?;
+    #[cfg(unix)]
+    let foreground = if deadline.detach {
+        None
+    } else {
+        Foreground::give(child.id())
+    };
+
+    let stdout = read(child.stdout.take(), capture, false, io::stdout);
+    let stderr = read(child.stderr.take(), capture, true, io::stderr);
+    let status = wait_until(&mut child, deadline);
+    #[cfg(unix)]
+    drop(foreground);
+
+    let mut killed = matches!(status, Ok(None));
+    let stdout = join(stdout, deadline, child.id(), &mut killed);
+    let stderr = join(stderr, deadline, child.id(), &mut killed);
+
+    match status.with_context(|| format!("Failed to wait for `{command}`"))? {
+        Some(status) if !killed => {
+            // The command had the terminal, so it got the Ctrl+C instead of Topgrade.
+            #[cfg(unix)]
+            if std::os::unix::process::ExitStatusExt::signal(&status) == Some(nix::libc::SIGINT) {
+                crate::ctrlc::set_interrupted();
+            }
+            Ok(Output { status, stdout, stderr })
+        }
+        _ => {
+            debug!("Killed `{command}` at the deadline of the step");
+            TIMED_OUT.set(true);
+            Err(StepTimedOut(deadline.timeout).into())
+        }
+    }
+}
+
+/// Read `pipe` to the end on a thread of its own, as `drain` does, sending the result once done.
+fn read<W: Write + 'static>(
+    pipe: Option<impl Read + Send + 'static>,
+    capture: bool,
+    keep: bool,
+    terminal: fn() -> W,
+) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || sender.send(drain(pipe, capture, keep, terminal())));
+    receiver
+}
+
+/// Wait for the output read by `read` until `deadline`. The pipes stay open as long as any process
+/// the command started is alive, so its process group is killed at the deadline unless it was
+/// already. The output is given up on if a process that left the group still holds the pipe.
+fn join(output: mpsc::Receiver<Vec<u8>>, deadline: Deadline, group: u32, killed: &mut bool) -> Vec<u8> {
+    match output.recv_timeout(deadline.at.saturating_duration_since(Instant::now())) {
+        Ok(output) => return output,
+        Err(mpsc::RecvTimeoutError::Disconnected) => return Vec::new(),
+        Err(mpsc::RecvTimeoutError::Timeout) => (),
+    }
+
+    if !*killed {
+        *killed = true;
+        kill_group(group);
+    }
+    output.recv_timeout(KILL_GRACE_PERIOD).unwrap_or_default()
+}
+
+/// Makes the process group of a command the foreground process group of the terminal, so that it
+/// can read from it, until dropped.
+#[cfg(unix)]
+struct Foreground;
+
+#[cfg(unix)]
+impl Foreground {
+    /// Hand the terminal over to the process group `group`, if Topgrade is in the foreground of the
+    /// terminal.
+    fn give(group: u32) -> Option<Self> {
+        use nix::sys::signal::{killpg, Signal};
+        use nix::unistd::{getpgrp, tcgetpgrp, tcsetpgrp, Pid};
+
+        let group = Pid::from_raw(group as i32);
+        if tcgetpgrp(io::stdin()).ok()? != getpgrp() {
+            return None;
+        }
+        tcsetpgrp(io::stdin(), group).ok()?;
+        // The command is stopped if it used the terminal before it was handed over.
+        killpg(group, Signal::SIGCONT).ok();
+        Some(Foreground)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        use nix::sys::signal::{SigSet, Signal};
+        use nix::unistd::{getpgrp, tcsetpgrp};
+
+        // Topgrade is in the background until it takes the terminal back, for which it would be
+        // stopped.
+        let mut ttou = SigSet::empty();
+        ttou.add(Signal::SIGTTOU);
+        ttou.thread_block().ok();
+        tcsetpgrp(io::stdin(), getpgrp()).ok();
+        ttou.thread_unblock().ok();
+    }
+}
+
+/// Read `pipe` to the end, either collecting what it produces or copying it to `terminal`, in
+/// which case a copy is only returned if `keep` is set.
+fn drain(pipe: Option<impl Read>, capture: bool, keep: bool, terminal: impl Write) -> Vec<u8> {
+    let mut output = Vec::new();
+    match pipe {
+        Some(mut pipe) if capture => {
+            pipe.read_to_end(&mut output).ok();
+        }
+        Some(pipe) if keep => {
+            let mut copying = Copying {
+                inner: terminal,
+                copy: output,
+            };
+            log_file::tee(pipe, &mut copying);
+            output = copying.copy;
+        }
+        Some(pipe) => log_file::tee(pipe, terminal),
+        None => (),
+    }
+    output
+}
+
+/// Writes to `inner`, keeping a copy of everything written.
+struct Copying<W> {
+    inner: W,
+    copy: Vec<u8>,
+}
+
+impl<W: Write> Write for Copying<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.copy.extend_from_slice(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wait for `child` to exit, or kill it at `deadline`, in which case this returns `None`.
+fn wait_until(child: &mut Child, deadline: Deadline) -> io::Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline.at {
+            kill(child);
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Wait up to `period` for `child` to exit, returning whether it did.
+fn exits_within(child: &mut Child, period: Duration) -> bool {
+    let until = Instant::now() + period;
+    loop {
+        if matches!(child.try_wait(), Ok(Some(_))) {
+            return true;
+        }
+        if Instant::now() >= until {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Terminate the process group of `child`, then kill what is left of it after a grace period.
+/// A child that even survives that, e.g. because it runs as another user, is given up on.
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+
+    let group = Pid::from_raw(child.id() as i32);
+    if killpg(group, Signal::SIGTERM).is_ok() {
+        exits_within(child, KILL_GRACE_PERIOD);
+    }
+    // The command may be gone while the processes it started are still running.
+    kill_group(child.id());
+    exits_within(child, KILL_GRACE_PERIOD);
+}
+
+#[cfg(windows)]
+fn kill(child: &mut Child) {
+    child.kill().ok();
+    exits_within(child, KILL_GRACE_PERIOD);
+}
+
+/// Kill what is left of the process group `group`.
+#[cfg(unix)]
+fn kill_group(group: u32) {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+
+    killpg(Pid::from_raw(group as i32), Signal::SIGKILL).ok();
+}
+
+#[cfg(windows)]
+fn kill_group(_group: u32) {}
+
 /// Like [`Output`], but UTF-8 decoded.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Utf8Output {
@@ -159,12 +500,7 @@ impl CommandExt for Command {
     fn output_checked_with(&mut self, succeeded: impl Fn(&Output) -> Result<(), ()>) -> eyre::Result<Output> {
         let command = log(self);
 
-        // This is where we implement `output_checked`, which is what we prefer to use instead of
-        // `output`, so we allow `Command::output` here.
-        #[allow(clippy::disallowed_methods)]
-        let output = self
-            .output()
-            .with_context(|| format!("Failed to execute `{command}`"))?;
+        let output = output(self)?;
 
         if succeeded(&output).is_ok() {
             Ok(output)
@@ -194,11 +530,51 @@ impl CommandExt for Command {
     fn status_checked_with(&mut self, succeeded: impl Fn(ExitStatus) -> Result<(), ()>) -> eyre::Result<()> {
         let command = log(self);
         let message = format!("Failed to execute `{command}`");
+        if is_unattended() {
+            self.stdin(Stdio::null());
+        }
 
-        // This is where we implement `status_checked`, which is what we prefer to use instead of
-        // `status`, so we allow `Command::status` here.
-        #[allow(clippy::disallowed_methods)]
-        let status = self.status().with_context(|| message.clone())?;
+        let status = if let Some(deadline) = DEADLINE.get() {
+            let output = run_until(self, deadline, is_capturing(), &command)?;
+            record(&output.stderr);
+            write_captured(&String::from_utf8_lossy(&output.stdout));
+            write_captured(&String::from_utf8_lossy(&output.stderr));
+            output.status
+        } else if is_capturing() {
+            // The step's output is being buffered, so collect the output instead of letting the
+            // command write to the terminal. There is nobody to answer prompts either.
+            #[allow(clippy::disallowed_methods)]
+            let output = self.stdin(Stdio::null()).output().with_context(|| message.clone())?;
+            record(&output.stderr);
+            write_captured(&String::from_utf8_lossy(&output.stdout));
+            write_captured(&String::from_utf8_lossy(&output.stderr));
+            output.status
+        } else if is_logging() || is_recording_stderr() {
+            // Copy the output of the command to the log file on its way to the terminal, keeping
+            // its standard error for the retry policy of the step.
+            #[allow(clippy::disallowed_methods)]
+            let mut child = self
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .with_context(|| message.clone())?;
+            let (stdout, stderr) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+            let mut copying = Copying {
+                inner: io::stderr(),
+                copy: Vec::new(),
+            };
+            thread::scope(|scope| {
+                scope.spawn(|| log_file::tee(stdout, io::stdout()));
+                log_file::tee(stderr, &mut copying);
+            });
+            record(&copying.copy);
+            child.wait().with_context(|| message.clone())?
+        } else {
+            // This is where we implement `status_checked`, which is what we prefer to use instead of
+            // `status`, so we allow `Command::status` here.
+            #[allow(clippy::disallowed_methods)]
+            self.status().with_context(|| message.clone())?
+        };
 
         if succeeded(status).is_ok() {
             Ok(())
@@ -243,5 +619,41 @@ fn format_program_and_args(cmd: &Command) -> String {
 fn log(cmd: &Command) -> String {
     let command = format_program_and_args(cmd);
     debug!("Executing command `{command}`");
+    log_file::write(&format!("$ {command}\n"));
     command
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timeout_kills_what_the_command_started() {
+        use std::fs;
+
+        for detach in [true, false] {
+            let pid_file = tempfile::NamedTempFile::new().unwrap();
+            let script = format!("sleep 60 & echo $! > {}; wait", pid_file.path().display());
+            let started = Instant::now();
+            let (result, timed_out) = with_timeout(Some(Duration::from_secs(1)), detach, || {
+                Command::new("sh").args(["-c", &script]).output_checked()
+            });
+
+            assert!(result.unwrap_err().is::<StepTimedOut>());
+            assert!(timed_out);
+            assert!(started.elapsed() < KILL_GRACE_PERIOD);
+
+            // The grandchild holds the pipes of the command until it is killed too. It was sent the
+            // signal, but may take a moment to exit.
+            let grandchild = fs::read_to_string(pid_file.path()).unwrap();
+            let stat = || fs::read_to_string(format!("/proc/{}/stat", grandchild.trim())).unwrap_or_default();
+            let exited = Instant::now() + Duration::from_secs(1);
+            while !(stat().is_empty() || stat().contains(") Z ")) && Instant::now() < exited {
+                thread::sleep(Duration::from_millis(10));
+            }
+            let stat = stat();
+            assert!(stat.is_empty() || stat.contains(") Z "), "{stat}");
+        }
+    }
+}
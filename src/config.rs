@@ -1,29 +1,36 @@
 #![allow(dead_code)]
 
-use std::fs::{write, File};
-use std::io::Write;
+use std::borrow::Cow;
+use std::fs::write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::LazyLock;
+use std::time::Duration;
 use std::{env, fmt, fs};
 
-use clap::{Parser, ValueEnum};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
 use color_eyre::eyre::Context;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, Result};
+use console::style;
 use etcetera::base_strategy::BaseStrategy;
 use indexmap::IndexMap;
 use merge::Merge;
 use regex::Regex;
 use regex_split::RegexSplit;
 use rust_i18n::t;
-use serde::Deserialize;
-use strum::{EnumIter, EnumString, IntoEnumIterator, VariantNames};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator, VariantNames};
 use which_crate::which;
 
 use super::utils::editor;
 use crate::command::CommandExt;
+use crate::error::ConfigError;
+use crate::migration;
+use crate::notify::{CommandHook, Email, Gotify, Notifier, Ntfy, Webhook};
+use crate::report::ReportFormat;
 use crate::sudo::SudoKind;
-use crate::utils::string_prepend_str;
+use crate::utils::merge_strategies::inner_merge_opt;
 use tracing::{debug, error};
 
 // TODO: Add i18n to this. Tracking issue: https://github.com/topgrade-rs/topgrade/issues/859
@@ -46,7 +53,21 @@ macro_rules! str_value {
 
 pub type Commands = IndexMap<String, String>;
 
-#[derive(ValueEnum, EnumString, VariantNames, Debug, Clone, PartialEq, Eq, Deserialize, EnumIter, Copy)]
+#[derive(
+    ValueEnum,
+    EnumString,
+    VariantNames,
+    Display,
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    Deserialize,
+    Serialize,
+    EnumIter,
+    Copy,
+)]
 #[clap(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
@@ -196,14 +217,423 @@ pub enum Step {
     Zvm,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+/// What a step needs from its environment, which decides whether it can run concurrently with
+/// other steps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepKind {
+    /// Needs nothing but the network and the user's files.
+    Unattended,
+    /// May read from or take over the terminal, e.g. to prompt the user or run an editor.
+    Interactive,
+    /// May run commands through `sudo` or its counterparts.
+    Elevated,
+}
+
+impl Step {
+    pub fn kind(self) -> StepKind {
+        match self {
+            // Brew runs through `sudo` on Linux when it is owned by another user.
+            Step::BrewFormula if cfg!(target_os = "linux") => StepKind::Elevated,
+            Step::AM
+            | Step::Audit
+            | Step::AutoCpufreq
+            | Step::BrewCask
+            | Step::Certbot
+            | Step::Chocolatey
+            | Step::Composer
+            | Step::ConfigUpdate
+            | Step::DebGet
+            | Step::DkpPacman
+            | Step::Firmware
+            | Step::Flatpak
+            | Step::Haxelib
+            | Step::Lensfun
+            | Step::Lure
+            | Step::Macports
+            | Step::Maza
+            | Step::Nix
+            | Step::Node
+            | Step::Pacdef
+            | Step::Pacstall
+            | Step::Pkg
+            | Step::Pkgin
+            | Step::Pnpm
+            | Step::Restarts
+            | Step::RubyGems
+            | Step::Snap
+            | Step::System
+            | Step::Tlmgr
+            | Step::Vcpkg
+            | Step::Waydroid
+            | Step::Winget
+            | Step::WslUpdate
+            | Step::Yarn => StepKind::Elevated,
+            Step::Chezmoi
+            | Step::CustomCommands
+            | Step::Distrobox
+            | Step::Emacs
+            | Step::Kakoune
+            | Step::Mas
+            | Step::MicrosoftStore
+            | Step::Powershell
+            | Step::Rcm
+            | Step::Remotes
+            | Step::Sdkman
+            | Step::SelfUpdate
+            | Step::Shell
+            | Step::Sparkle
+            | Step::Toolbx
+            | Step::Vagrant
+            | Step::Vim
+            | Step::Wsl
+            | Step::Xcodes => StepKind::Interactive,
+            Step::AndroidStudio
+            | Step::AppMan
+            | Step::Aqua
+            | Step::Asdf
+            | Step::Atom
+            | Step::Bin
+            | Step::Bob
+            | Step::BrewFormula
+            | Step::Bun
+            | Step::BunPackages
+            | Step::Cargo
+            | Step::Choosenim
+            | Step::CinnamonSpices
+            | Step::ClamAvDb
+            | Step::Conda
+            | Step::Containers
+            | Step::Deno
+            | Step::Dotnet
+            | Step::Elan
+            | Step::Flutter
+            | Step::Fossil
+            | Step::Gcloud
+            | Step::Gem
+            | Step::Ghcup
+            | Step::GitRepos
+            | Step::GithubCliExtensions
+            | Step::GnomeShellExtensions
+            | Step::Go
+            | Step::Guix
+            | Step::Helix
+            | Step::Helm
+            | Step::HomeManager
+            | Step::JetbrainsAqua
+            | Step::JetbrainsClion
+            | Step::JetbrainsDatagrip
+            | Step::JetbrainsDataspell
+            | Step::JetbrainsGateway
+            | Step::JetbrainsGoland
+            | Step::JetbrainsIdea
+            | Step::JetbrainsMps
+            | Step::JetbrainsPhpstorm
+            | Step::JetbrainsPycharm
+            | Step::JetbrainsRider
+            | Step::JetbrainsRubymine
+            | Step::JetbrainsRustrover
+            | Step::JetbrainsToolbox
+            | Step::JetbrainsWebstorm
+            | Step::Jetpack
+            | Step::Julia
+            | Step::Juliaup
+            | Step::Krew
+            | Step::Mamba
+            | Step::Micro
+            | Step::Miktex
+            | Step::Mise
+            | Step::Myrepos
+            | Step::Opam
+            | Step::Pearl
+            | Step::Pip3
+            | Step::PipReview
+            | Step::PipReviewLocal
+            | Step::Pipupgrade
+            | Step::Pipx
+            | Step::Pipxu
+            | Step::Pixi
+            | Step::PlatformioCore
+            | Step::Poetry
+            | Step::Protonup
+            | Step::Pyenv
+            | Step::Raco
+            | Step::Rtcl
+            | Step::Rustup
+            | Step::Rye
+            | Step::Scoop
+            | Step::Sheldon
+            | Step::Spicetify
+            | Step::Stack
+            | Step::Stew
+            | Step::Tldr
+            | Step::Tmux
+            | Step::Uv
+            | Step::VoltaPackages
+            | Step::Vscode
+            | Step::Vscodium
+            | Step::Yadm
+            | Step::Yazi
+            | Step::Zigup
+            | Step::Zvm => StepKind::Unattended,
+        }
+    }
+
+    pub fn interactive(self) -> bool {
+        self.kind() == StepKind::Interactive
+    }
+
+    pub fn elevated(self) -> bool {
+        self.kind() == StepKind::Elevated
+    }
+
+    /// Tell whether the step can run concurrently with other steps.
+    pub fn parallelizable(self) -> bool {
+        self.kind() == StepKind::Unattended
+    }
+
+    /// Pairs of steps where the first one has to run before the second one, e.g. because the
+    /// first one updates the tool that the second one uses.
+    pub const ORDERING: &'static [(Step, Step)] = &[
+        (Step::BrewFormula, Step::BrewCask),
+        (Step::Conda, Step::Mamba),
+        (Step::Node, Step::Pnpm),
+        (Step::Node, Step::Yarn),
+        (Step::Pip3, Step::PipReview),
+        (Step::Pip3, Step::PipReviewLocal),
+        (Step::Pip3, Step::Pipupgrade),
+        (Step::Rustup, Step::Cargo),
+        (Step::System, Step::Firmware),
+        (Step::System, Step::Restarts),
+    ];
+
+    /// Tell whether the two steps must not run at the same time, usually because they modify the
+    /// same files. Different entries of the same step always conflict.
+    pub fn conflicts_with(self, other: Step) -> bool {
+        const CONFLICTS: &[(Step, Step)] = &[
+            (Step::BrewCask, Step::BrewFormula),
+            (Step::Cargo, Step::Rustup),
+            (Step::Conda, Step::Mamba),
+            (Step::Node, Step::Pnpm),
+            (Step::Node, Step::Yarn),
+            (Step::Pip3, Step::PipReview),
+            (Step::Pip3, Step::PipReviewLocal),
+            (Step::Pip3, Step::Pipupgrade),
+            (Step::PipReview, Step::PipReviewLocal),
+            (Step::PipReview, Step::Pipupgrade),
+            (Step::PipReviewLocal, Step::Pipupgrade),
+            (Step::Pipx, Step::Pipxu),
+        ];
+
+        self == other
+            || CONFLICTS
+                .iter()
+                .any(|&(a, b)| (a, b) == (self, other) || (b, a) == (self, other))
+    }
+}
+
+/// Settings of a single step, from the `[step.<name>]` sections.
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct StepConfig {
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    after: Option<Vec<Step>>,
+
+    #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
+    before: Option<Vec<Step>>,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::utils::deserialize_duration",
+        serialize_with = "crate::utils::serialize_duration"
+    )]
+    timeout: Option<Duration>,
+
+    auto_retry: Option<AutoRetry>,
+}
+
+/// Commands run around a step, from the `[hooks.<name>]` sections.
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    pre: Option<String>,
+    post: Option<String>,
+    on_failure: Option<String>,
+}
+
+/// When a hook of a step is run.
+#[derive(Clone, Copy, Debug, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Hook {
+    /// Before the step; the step is skipped if the hook fails.
+    Pre,
+    /// After the step, unless it was skipped.
+    Post,
+    /// After the step, if it failed.
+    OnFailure,
+}
+
+/// Which log files or records of past runs to keep, from `[misc] log_rotation` and
+/// `[misc] history_rotation`.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct LogRotation {
+    keep: Option<usize>,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::utils::deserialize_duration",
+        serialize_with = "crate::utils::serialize_duration"
+    )]
+    max_age: Option<Duration>,
+
+    max_size: Option<u64>,
+}
+
+impl LogRotation {
+    /// How many runs to keep the log of, including the current one.
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(10).max(1)
+    }
+
+    /// Logs older than this are removed.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// The oldest logs are removed while all the logs take more than this many bytes.
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size.map(|mib| mib * 1024 * 1024)
+    }
+}
+
+/// Errors on which failed steps are retried by default.
+static TRANSIENT_ERRORS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        "Could not resolve host",
+        "Temporary failure",
+        "Connection timed out",
+        "Connection reset",
+        r"\b50[234]\b",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect()
+});
+
+/// When to retry failed steps without asking, from `[misc] auto_retry` and the `auto_retry` of
+/// `[step.<name>]`, whose settings take precedence.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct AutoRetry {
+    attempts: Option<u32>,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::utils::deserialize_duration",
+        serialize_with = "crate::utils::serialize_duration"
+    )]
+    backoff: Option<Duration>,
+
+    #[serde(
+        default,
+        deserialize_with = "deserialize_patterns",
+        serialize_with = "serialize_patterns"
+    )]
+    patterns: Option<Vec<Regex>>,
+}
+
+fn deserialize_patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<Regex>>, D::Error> {
+    Option::<Vec<String>>::deserialize(deserializer)?
+        .map(|patterns| {
+            patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern).map_err(de::Error::custom))
+                .collect()
+        })
+        .transpose()
+}
+
+/// Profiles only overlay settings: they cannot include files, have profiles or a version.
+fn deserialize_profiles<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<IndexMap<String, ConfigFile>>, D::Error> {
+    let profiles = Option::<IndexMap<String, ConfigFile>>::deserialize(deserializer)?;
+    for (name, profile) in profiles.iter().flatten() {
+        let key = if profile.include.is_some() {
+            "include"
+        } else if profile.profile.is_some() {
+            "profile"
+        } else if profile.version.is_some() {
+            "version"
+        } else {
+            continue;
+        };
+        return Err(de::Error::custom(t!(
+            "The profile {name} cannot set `{key}`",
+            name = name,
+            key = key
+        )));
+    }
+    Ok(profiles)
+}
+
+fn serialize_patterns<S: serde::Serializer>(patterns: &Option<Vec<Regex>>, serializer: S) -> Result<S::Ok, S::Error> {
+    patterns
+        .as_ref()
+        .map(|patterns| patterns.iter().map(Regex::as_str).collect::<Vec<_>>())
+        .serialize(serializer)
+}
+
+impl AutoRetry {
+    /// How many times a step runs at most, including the first time.
+    pub fn attempts(&self) -> u32 {
+        self.attempts.unwrap_or(3)
+    }
+
+    /// How long to wait before running a step for the `attempt`th time. The wait doubles after
+    /// every retry.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff.unwrap_or(Duration::from_secs(30));
+        backoff.saturating_mul(1 << attempt.saturating_sub(2).min(16))
+    }
+
+    /// Tells whether a failure with this error message or output looks transient.
+    pub fn is_transient(&self, output: &str) -> bool {
+        self.patterns
+            .as_ref()
+            .unwrap_or(&TRANSIENT_ERRORS)
+            .iter()
+            .any(|pattern| pattern.is_match(output))
+    }
+}
+
+/// Where to send notifications about runs, from the `[notify]` section.
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
+#[serde(deny_unknown_fields)]
+pub struct Notify {
+    only_on_failure: Option<bool>,
+
+    each_failed_step: Option<bool>,
+
+    command: Option<CommandHook>,
+
+    webhook: Option<Webhook>,
+
+    ntfy: Option<Ntfy>,
+
+    gotify: Option<Gotify>,
+
+    email: Option<Email>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Include {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     paths: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Containers {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
@@ -211,7 +641,7 @@ pub struct Containers {
     runtime: Option<ContainerRuntime>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Git {
     max_concurrency: Option<usize>,
@@ -225,7 +655,7 @@ pub struct Git {
     pull_predefined: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Vagrant {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
@@ -235,7 +665,7 @@ pub struct Vagrant {
     always_suspend: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Windows {
     accept_all_updates: Option<bool>,
@@ -247,7 +677,7 @@ pub struct Windows {
     winget_use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Python {
     enable_pip_review: Option<bool>,
@@ -257,7 +687,7 @@ pub struct Python {
     poetry_force_self_update: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct Distrobox {
@@ -267,42 +697,42 @@ pub struct Distrobox {
     containers: Option<Vec<String>>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct Yarn {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct NPM {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct Deno {
     version: Option<String>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct Firmware {
     upgrade: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct Flatpak {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Brew {
     greedy_cask: Option<bool>,
@@ -312,7 +742,7 @@ pub struct Brew {
     fetch_head: Option<bool>,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub enum ArchPackageManager {
     Autodetect,
@@ -326,7 +756,18 @@ pub enum ArchPackageManager {
     Yay,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+/// The tool taking the filesystem snapshots around system upgrades, from `[linux] snapshot`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SnapshotTool {
+    Snapper,
+    Btrfs,
+    Timeshift,
+    Zfs,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ContainerRuntime {
     Docker,
@@ -342,7 +783,7 @@ impl fmt::Display for ContainerRuntime {
     }
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Linux {
     #[merge(strategy = crate::utils::merge_strategies::string_append_opt)]
@@ -394,21 +835,25 @@ pub struct Linux {
 
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     home_manager_arguments: Option<Vec<String>>,
+
+    snapshot: Option<SnapshotTool>,
+    snapshot_after: Option<bool>,
+    btrfs_snapshot_dir: Option<PathBuf>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Composer {
     self_update: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Vim {
     force_plug_update: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Misc {
     pre_sudo: Option<bool>,
@@ -421,6 +866,8 @@ pub struct Misc {
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     ignore_failures: Option<Vec<Step>>,
 
+    count_ignored_failures: Option<bool>,
+
     #[merge(strategy = crate::utils::merge_strategies::vec_prepend_opt)]
     remote_topgrades: Option<Vec<String>>,
 
@@ -458,9 +905,40 @@ pub struct Misc {
     no_self_update: Option<bool>,
 
     log_filters: Option<Vec<String>>,
+
+    parallelism: Option<usize>,
+
+    order: Option<Vec<Step>>,
+
+    log_file: Option<bool>,
+
+    log_rotation: Option<LogRotation>,
+
+    history_rotation: Option<LogRotation>,
+
+    #[serde(
+        default,
+        deserialize_with = "crate::utils::deserialize_duration",
+        serialize_with = "crate::utils::serialize_duration"
+    )]
+    step_timeout: Option<Duration>,
+
+    auto_retry: Option<AutoRetry>,
+
+    auto_reboot: Option<AutoReboot>,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, ValueEnum)]
+/// Whether to reboot at the end of the run, from `[misc] auto_reboot`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoReboot {
+    #[default]
+    Never,
+    /// Reboot if an upgrade requires it and no step failed.
+    IfRequired,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, ValueEnum)]
 #[clap(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
 pub enum TmuxSessionMode {
@@ -473,19 +951,19 @@ pub struct TmuxConfig {
     pub session_mode: TmuxSessionMode,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Lensfun {
     use_sudo: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct JuliaConfig {
     startup_file: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct Zigup {
     target_versions: Option<Vec<String>>,
@@ -494,16 +972,19 @@ pub struct Zigup {
     cleanup: Option<bool>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 pub struct VscodeConfig {
     profile: Option<String>,
 }
 
-#[derive(Deserialize, Default, Debug, Merge)]
+#[derive(Deserialize, Serialize, Default, Debug, Merge)]
 #[serde(deny_unknown_fields)]
 /// Configuration file
 pub struct ConfigFile {
+    /// The version of the format of the file, see `crate::migration`.
+    version: Option<i64>,
+
     #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
     include: Option<Include>,
 
@@ -575,6 +1056,20 @@ pub struct ConfigFile {
 
     #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
     vscode: Option<VscodeConfig>,
+
+    #[merge(strategy = crate::utils::merge_strategies::map_merge_opt)]
+    step: Option<IndexMap<Step, StepConfig>>,
+
+    #[merge(strategy = crate::utils::merge_strategies::map_merge_opt)]
+    hooks: Option<IndexMap<Step, Hooks>>,
+
+    #[merge(strategy = crate::utils::merge_strategies::inner_merge_opt)]
+    notify: Option<Notify>,
+
+    /// Named sets of settings that overlay the others, selected with `--profile`.
+    #[merge(strategy = crate::utils::merge_strategies::map_merge_opt)]
+    #[serde(default, deserialize_with = "deserialize_profiles")]
+    profile: Option<IndexMap<String, ConfigFile>>,
 }
 
 fn config_directory() -> PathBuf {
@@ -591,6 +1086,273 @@ struct ConfigFileIncludeOnly {
     include: Option<Include>,
 }
 
+/// A configuration file, a part of one, or an environment variable, as it was merged into the
+/// configuration.
+#[derive(Debug)]
+struct Source {
+    /// Where the source comes from, as `config show --origin` prints it.
+    origin: String,
+    table: toml::Table,
+}
+
+impl Source {
+    fn new(path: &Path, contents: &str) -> Option<Self> {
+        let table = toml::from_str(contents).ok()?;
+        Some(Self {
+            origin: path.display().to_string(),
+            table,
+        })
+    }
+
+    /// The profile `name` defined in this source, if any.
+    fn profile(&self, name: &str) -> Option<Self> {
+        let table = self.table.get("profile")?.get(name)?.as_table()?.clone();
+        Some(Self {
+            origin: format!("{} [profile.{name}]", self.origin),
+            table,
+        })
+    }
+}
+
+/// The prefix of the environment variables that set a configuration key, such as
+/// `TOPGRADE__MISC__NO_RETRY`.
+const ENV_PREFIX: &str = "TOPGRADE__";
+
+/// The environment variables that set a configuration key, sorted by name.
+fn env_variables() -> Vec<(String, String)> {
+    let mut variables: Vec<_> = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    variables.sort();
+    variables
+}
+
+/// The configuration set by the environment variable `name`, whose value is read as TOML, or
+/// else as a string, along with the configuration as a table.
+fn env_config(name: &str, value: &str) -> Result<(ConfigFile, toml::Table), toml::de::Error> {
+    let keys: Vec<_> = name[ENV_PREFIX.len()..].split("__").map(str::to_lowercase).collect();
+    let parsed = toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"));
+
+    let mut error = None;
+    for value in parsed.into_iter().chain([value.into()]) {
+        let toml::Value::Table(table) = keys.iter().rev().fold(value, |value, key| {
+            toml::Value::Table(toml::Table::from_iter([(key.clone(), value)]))
+        }) else {
+            unreachable!("There is at least one key");
+        };
+        match toml::Value::Table(table.clone()).try_into() {
+            Ok(config_file) => return Ok((config_file, table)),
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+
+    Err(error.expect("at least one value was tried"))
+}
+
+/// Collect the values in `table` that are not tables, along with their keys.
+fn collect_values<'t>(
+    prefix: &mut Vec<&'t str>,
+    table: &'t toml::Table,
+    values: &mut Vec<(Vec<&'t str>, &'t toml::Value)>,
+) {
+    for (key, value) in table {
+        prefix.push(key);
+        match value {
+            toml::Value::Table(table) => collect_values(prefix, table, values),
+            value => values.push((prefix.clone(), value)),
+        }
+        prefix.pop();
+    }
+}
+
+/// The keys of the secrets that `topgrade config show` hides, with every value below them.
+const SECRETS: [&[&str]; 6] = [
+    &["notify", "webhook", "url"],
+    &["notify", "webhook", "headers"],
+    &["notify", "ntfy", "url"],
+    &["notify", "ntfy", "token"],
+    &["notify", "gotify", "token"],
+    &["notify", "email", "password"],
+];
+
+/// Whether the value at the dotted `key` is a secret, in the file or in one of its profiles.
+fn is_secret(key: &[&str]) -> bool {
+    let key = match key {
+        ["profile", _, key @ ..] => key,
+        key => key,
+    };
+    SECRETS.iter().any(|secret| key.starts_with(secret))
+}
+
+/// Replace the secrets in `table`, whose values are at the dotted `prefix`, with `"***"`.
+fn redact_secrets<'t>(prefix: &mut Vec<&'t str>, table: &'t mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        prefix.push(key);
+        match value {
+            toml::Value::Table(table) => redact_secrets(prefix, table),
+            value if is_secret(prefix) => *value = "***".into(),
+            _ => (),
+        }
+        prefix.pop();
+    }
+}
+
+/// The value at the dotted `key` of `table`.
+fn lookup<'t>(table: &'t toml::Table, key: &[&str]) -> Option<&'t toml::Value> {
+    let (last, tables) = key.split_last()?;
+    let mut table = table;
+    for key in tables {
+        table = table.get(*key)?.as_table()?;
+    }
+    table.get(*last)
+}
+
+/// A key as written in TOML, quoted unless it is a bare key.
+fn toml_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        key.to_string()
+    } else {
+        toml::Value::from(key).to_string()
+    }
+}
+
+/// A problem found in a configuration file by `topgrade config check`.
+#[derive(Debug)]
+pub struct Diagnostic {
+    path: PathBuf,
+    /// The line and the column of the problem, both starting at 1.
+    position: Option<(usize, usize)>,
+    message: String,
+}
+
+impl Diagnostic {
+    /// A problem at the byte `offset` of `source`, the contents of `path`.
+    fn new(path: &Path, source: &str, offset: Option<usize>, mut message: String) -> Self {
+        // Listing every step does not help fixing the name of one.
+        if let Some(expected) = message.find(", expected one of ") {
+            if message[expected..].matches(", ").count() > 50 {
+                message.truncate(expected);
+            }
+        }
+
+        let position = offset.map(|offset| {
+            let before = &source[..offset.min(source.len())];
+            let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+            (
+                before.matches('\n').count() + 1,
+                before[line_start..].chars().count() + 1,
+            )
+        });
+        Self {
+            path: path.to_path_buf(),
+            position,
+            message,
+        }
+    }
+
+    /// Check a whole configuration file. A file in an older format is checked once migrated, as it
+    /// is read, without the positions of the problems.
+    fn check_file(path: &Path, diagnostics: &mut Vec<Diagnostic>) {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => return diagnostics.push(Self::new(path, "", None, e.to_string())),
+        };
+        match migration::migrated(path, &contents) {
+            Cow::Borrowed(contents) => Self::check_source(path, contents, 0, contents, diagnostics),
+            Cow::Owned(migrated) => {
+                let checked = diagnostics.len();
+                Self::check_source(path, &migrated, 0, &migrated, diagnostics);
+                for diagnostic in &mut diagnostics[checked..] {
+                    diagnostic.position = None;
+                }
+            }
+        }
+    }
+
+    /// Check `part` of the contents of a configuration file, which starts at the byte `offset` of
+    /// `source`.
+    fn check_source(path: &Path, source: &str, offset: usize, part: &str, diagnostics: &mut Vec<Diagnostic>) {
+        if let Err(e) = part.parse::<toml::Table>() {
+            diagnostics.push(Self::new(
+                path,
+                source,
+                e.span().map(|span| span.start + offset),
+                e.message().to_string(),
+            ));
+            return;
+        }
+
+        // Deserializing stops at the first error, so the lines of each error are blanked out in
+        // turn to find the next one. Blanking keeps the positions of the remaining text.
+        let mut part = part.to_string();
+        while let Err(e) = toml::from_str::<ConfigFile>(&part) {
+            let span = e.span();
+            diagnostics.push(Self::new(
+                path,
+                source,
+                span.clone().map(|span| span.start + offset),
+                e.message().to_string(),
+            ));
+            if !span.is_some_and(|span| blank_lines(&mut part, span)) {
+                break;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some((line, column)) = self.position {
+            write!(f, ":{line}:{column}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Split the contents of a configuration file before each of its `[include]` sections, so that
+/// every part can be read as a separate file, in the order they are written.
+pub(crate) fn split_includes(contents: &str) -> Vec<&str> {
+    static INCLUDE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?m)^\s*\[include]").unwrap());
+    INCLUDE.split_inclusive_left(contents).collect()
+}
+
+/// Replace the lines covering `span` of `contents` with spaces, returning whether anything was left
+/// to blank. The lines of a table header take the whole table with them.
+fn blank_lines(contents: &mut String, span: std::ops::Range<usize>) -> bool {
+    let start = contents[..span.start].rfind('\n').map_or(0, |newline| newline + 1);
+    let line_end = |from: usize| {
+        contents[from..]
+            .find('\n')
+            .map_or(contents.len(), |newline| from + newline)
+    };
+    let mut end = line_end(span.end.max(start));
+    if contents[start..].trim_start().starts_with('[') {
+        while end < contents.len() && !contents[end + 1..].trim_start_matches([' ', '\t']).starts_with('[') {
+            end = line_end(end + 1);
+        }
+    }
+
+    if contents[start..end].trim().is_empty() {
+        return false;
+    }
+    let blanked: String = contents[start..end]
+        .chars()
+        .map(|c| {
+            if c == '\n' {
+                c.to_string()
+            } else {
+                " ".repeat(c.len_utf8())
+            }
+        })
+        .collect();
+    contents.replace_range(start..end, &blanked);
+    true
+}
+
 impl ConfigFile {
     /// Returns the main config file and any additional config files
     /// 0 = main config file
@@ -660,11 +1422,49 @@ impl ConfigFile {
         Ok(res)
     }
 
-    /// Read the configuration file.
+    /// Take the profiles out of the configuration, returning the profile `name` if it is defined.
+    /// Profiles cannot define other profiles.
+    fn take_profile(&mut self, name: &str) -> Option<ConfigFile> {
+        let mut profile = self.profile.take()?.shift_remove(name)?;
+        profile.profile = None;
+        Some(profile)
+    }
+
+    /// Read the `TOPGRADE__<SECTION>__<KEY>` environment variables as a configuration file,
+    /// along with whether all of them could be read. The variables that cannot be read are
+    /// skipped. The variables that were merged are added to `sources`.
+    fn from_env(sources: &mut Vec<Source>) -> (ConfigFile, bool) {
+        let mut result = Self::default();
+        let mut complete = true;
+
+        for (name, value) in env_variables() {
+            match env_config(&name, &value) {
+                Ok((config_file, table)) => {
+                    debug!("Configuration from {name}: {table}");
+                    result.merge(config_file);
+                    sources.push(Source {
+                        origin: format!("env {name}"),
+                        table,
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to read {name}: {e}");
+                    complete = false;
+                }
+            }
+        }
+
+        (result, complete)
+    }
+
+    /// Read the configuration file, along with whether all of it could be read. The parts that
+    /// cannot be read are skipped. The files that were merged are added to `sources`, in the
+    /// order of the merge.
     ///
     /// If the configuration file does not exist, the function returns the default ConfigFile.
-    fn read(config_path: Option<PathBuf>) -> Result<ConfigFile> {
+    fn read(config_path: Option<PathBuf>, sources: &mut Vec<Source>) -> Result<(ConfigFile, bool)> {
         let mut result = Self::default();
+        let mut complete = true;
 
         let config_path = if let Some(path) = config_path {
             path
@@ -679,11 +1479,13 @@ impl ConfigFile {
                 let include_contents = fs::read_to_string(&include).inspect_err(|_| {
                     error!("Unable to read {}", include.display());
                 })?;
-                let include_contents_parsed = toml::from_str(include_contents.as_str()).inspect_err(|_| {
+                let include_contents = migration::migrated(&include, &include_contents);
+                let include_contents_parsed = toml::from_str(&include_contents).inspect_err(|_| {
                     error!("Failed to deserialize {}", include.display());
                 })?;
 
                 result.merge(include_contents_parsed);
+                sources.extend(Source::new(&include, &include_contents));
             }
 
             path
@@ -692,21 +1494,17 @@ impl ConfigFile {
         if config_path == PathBuf::default() {
             // Here we expect topgrade.d and consequently result is not empty.
             // If empty, Self:: ensure() would have created the default config.
-            return Ok(result);
+            return Ok((result, complete));
         }
 
-        let mut contents_non_split = fs::read_to_string(&config_path).inspect_err(|_| {
+        let contents_non_split = fs::read_to_string(&config_path).inspect_err(|_| {
             error!("Unable to read {}", config_path.display());
         })?;
-
-        Self::ensure_misc_is_present(&mut contents_non_split, &config_path);
+        let contents_non_split = migration::migrated(&config_path, &contents_non_split);
 
         // To parse [include] sections in the order as they are written,
         // we split the file and parse each part as a separate file
-        let regex_match_include = Regex::new(r"^\s*\[include]").expect("Failed to compile regex");
-        let contents_split = regex_match_include.split_inclusive_left(contents_non_split.as_str());
-
-        for contents in contents_split {
+        for contents in split_includes(&contents_non_split) {
             let config_file_include_only: ConfigFileIncludeOnly = toml::from_str(contents).inspect_err(|_| {
                 error!("Failed to deserialize an include section of {}", config_path.display());
             })?;
@@ -721,13 +1519,19 @@ impl ConfigFile {
                             Ok(c) => c,
                             Err(e) => {
                                 error!("Unable to read {}: {e}", include_path.display(),);
+                                complete = false;
                                 continue;
                             }
                         };
+                        let include_contents = migration::migrated(&include_path, &include_contents);
                         match toml::from_str::<Self>(&include_contents) {
-                            Ok(include_parsed) => result.merge(include_parsed),
+                            Ok(include_parsed) => {
+                                result.merge(include_parsed);
+                                sources.extend(Source::new(&include_path, &include_contents));
+                            }
                             Err(e) => {
                                 error!("Failed to deserialize {}: {e}", include_path.display(),);
+                                complete = false;
                                 continue;
                             }
                         };
@@ -736,8 +1540,14 @@ impl ConfigFile {
             }
 
             match toml::from_str::<Self>(contents) {
-                Ok(contents) => result.merge(contents),
-                Err(e) => error!("Failed to deserialize {}: {e}", config_path.display(),),
+                Ok(parsed) => {
+                    result.merge(parsed);
+                    sources.extend(Source::new(&config_path, contents));
+                }
+                Err(e) => {
+                    error!("Failed to deserialize {}: {e}", config_path.display(),);
+                    complete = false;
+                }
             }
         }
 
@@ -753,7 +1563,88 @@ impl ConfigFile {
         }
 
         debug!("Loaded configuration: {:?}", result);
-        Ok(result)
+        Ok((result, complete))
+    }
+
+    /// Check the configuration files that `read` reads, and the environment variables that
+    /// `from_env` reads, reporting every problem in them instead of skipping the parts that cannot
+    /// be read. Returns how many files were checked.
+    fn check(config_path: Option<PathBuf>, diagnostics: &mut Vec<Diagnostic>) -> Result<usize> {
+        for (name, value) in env_variables() {
+            if let Err(e) = env_config(&name, &value) {
+                let message = e.to_string().trim_end().replace('\n', " ");
+                diagnostics.push(Diagnostic::new(Path::new(&name), "", None, message));
+            }
+        }
+
+        let mut files = 0;
+        let config_path = if let Some(path) = config_path {
+            path
+        } else {
+            let (path, dir_include) = Self::ensure()?;
+            for include in dir_include {
+                files += 1;
+                Diagnostic::check_file(&include, diagnostics);
+            }
+            path
+        };
+
+        if config_path == PathBuf::default() {
+            return Ok(files);
+        }
+
+        files += 1;
+        let contents = match fs::read_to_string(&config_path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                diagnostics.push(Diagnostic::new(&config_path, "", None, e.to_string()));
+                return Ok(files);
+            }
+        };
+
+        // A file in an older format is checked as it is read, once migrated. The positions in the
+        // migrated contents are not those in the file, so they are left out.
+        let checked = diagnostics.len();
+        let migrated = migration::migrated(&config_path, &contents);
+        for part in split_includes(&migrated) {
+            let offset = part.as_ptr() as usize - migrated.as_ptr() as usize;
+            if let Ok(ConfigFileIncludeOnly {
+                include: Some(Include { paths: Some(paths) }),
+            }) = toml::from_str(part)
+            {
+                for include in paths {
+                    files += 1;
+                    let include_path = shellexpand::tilde::<&str>(&include.as_ref()).into_owned();
+                    Diagnostic::check_file(Path::new(&include_path), diagnostics);
+                }
+            }
+            Diagnostic::check_source(&config_path, &migrated, offset, part, diagnostics);
+        }
+        if matches!(migrated, Cow::Owned(_)) {
+            for diagnostic in &mut diagnostics[checked..] {
+                if diagnostic.path == config_path {
+                    diagnostic.position = None;
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Migrate the configuration files that `read` reads, except the included files, which may
+    /// be shared with other machines.
+    fn migrate(config_path: Option<PathBuf>, dry_run: bool) -> Result<()> {
+        let paths = if let Some(path) = config_path {
+            vec![path]
+        } else {
+            let (path, mut paths) = Self::ensure()?;
+            if path != PathBuf::default() {
+                paths.push(path);
+            }
+            paths
+        };
+
+        migration::migrate_files(&paths, dry_run)
     }
 
     fn edit() -> Result<()> {
@@ -770,25 +1661,142 @@ impl ConfigFile {
             .status_checked()
             .context("Failed to open configuration file editor")
     }
+}
 
-    /// [Misc] was added later, here we check if it is present in the config file and add it if not
-    fn ensure_misc_is_present(contents: &mut String, path: &PathBuf) {
-        if !contents.contains("[misc]") {
-            debug!("Adding [misc] section to {}", path.display());
-            string_prepend_str(contents, "[misc]\n");
+/// Commands that do something else than upgrading the system.
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    /// Show which steps apply to this machine, without running any of them
+    Detect {
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the past runs
+    History {
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+
+        /// Only list the last N runs
+        #[arg(long, value_name = "N")]
+        last: Option<usize>,
+
+        /// Only list the runs in which the given step ran
+        #[arg(long, value_name = "STEP", value_enum)]
+        step: Option<Step>,
+
+        /// Only list the runs in which a step failed
+        #[arg(long)]
+        failed: bool,
+    },
+
+    /// Run Topgrade regularly
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommand,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Show the filesystem snapshots taken before the system upgrades, see `[linux] snapshot`.
+    /// Rolling back to one of them is left to the tool that took it
+    Rollback {
+        /// List the snapshots of every run, with the command rolling back to each of them
+        #[arg(long, required = true)]
+        list: bool,
+    },
+}
 
-            File::create(path)
-                .and_then(|mut f| f.write_all(contents.as_bytes()))
-                .expect("Tried to auto-migrate the config file, unable to write to config file.\nPlease add \"[misc]\" section manually to the first line of the file.\nError");
-        }
-    }
+#[derive(Subcommand, Debug)]
+pub enum ScheduleCommand {
+    /// Run `topgrade --unattended --yes` regularly, with a systemd user timer, or a crontab entry
+    /// where systemd is not available
+    #[command(group(ArgGroup::new("frequency").required(true)))]
+    Install {
+        /// Run every day
+        #[arg(long, group = "frequency")]
+        daily: bool,
+
+        /// Run every week
+        #[arg(long, group = "frequency")]
+        weekly: bool,
+
+        /// Use a crontab entry even if systemd is available
+        #[arg(long)]
+        cron: bool,
+    },
+
+    /// Remove the timer or crontab entry added by `topgrade schedule install`
+    Uninstall,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Check the configuration files, including the files in `topgrade.d` and the included
+    /// files, and report every error in them
+    Check,
+
+    /// Migrate the configuration files to the current format, keeping their comments. A backup
+    /// of every migrated file is written next to it
+    Migrate {
+        /// Only print the changes, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print the configuration merged from every file and from the command line
+    Show {
+        /// The format to print the configuration in
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Toml)]
+        format: ConfigFormat,
+
+        /// Annotate every value with the file it comes from, or with the command line
+        #[arg(long)]
+        origin: bool,
+
+        /// Print the passwords, tokens and notification URLs instead of hiding them
+        #[arg(long)]
+        show_secrets: bool,
+    },
+}
+
+/// The format of `topgrade config show`.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+#[clap(rename_all = "snake_case")]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// Show the results, errors and package changes of every step of a past run
+    Show {
+        /// The ID of the run, as listed by `topgrade history`
+        id: String,
+    },
 }
 
+/// The exit codes of Topgrade. When several apply, the first one wins.
+const EXIT_STATUS: &str = "\
+Exit status:
+  0   Everything went fine
+  1   A step failed
+  2   The configuration could not be loaded, or has errors with --strict
+  3   Quit by the user
+  10  A reboot is required to finish the upgrades
+  20  Updates are available, with --check";
+
 // Command line arguments
 // TODO: i18n of clap currently not easily possible. Waiting for https://github.com/clap-rs/clap/issues/380
 // Tracking issue for i18n: https://github.com/topgrade-rs/topgrade/issues/859
 #[derive(Parser, Debug)]
-#[command(name = "topgrade", version)]
+#[command(name = "topgrade", version, after_help = EXIT_STATUS)]
 pub struct CommandLineArgs {
     /// Edit the configuration file
     #[arg(long = "edit-config")]
@@ -810,6 +1818,26 @@ pub struct CommandLineArgs {
     #[arg(short = 'n', long = "dry-run")]
     dry_run: bool,
 
+    /// Never read from the terminal: answer no to every prompt and do not wait for a key press
+    #[arg(long = "unattended")]
+    unattended: bool,
+
+    /// Only list the available updates of the steps that support it, without upgrading anything
+    #[arg(long = "check", conflicts_with = "dry_run")]
+    check: bool,
+
+    /// Refuse to run if the configuration has errors, instead of ignoring the parts with errors
+    #[arg(long = "strict")]
+    strict: bool,
+
+    /// Skip the steps that succeeded in the last run if it was interrupted
+    #[arg(long = "resume", conflicts_with = "check")]
+    resume: bool,
+
+    /// Only run the steps that failed in the last run
+    #[arg(long = "rerun-failed", conflicts_with_all = ["check", "resume"])]
+    rerun_failed: bool,
+
     /// Do not ask to retry failed steps
     #[arg(long = "no-retry")]
     no_retry: bool,
@@ -860,6 +1888,10 @@ pub struct CommandLineArgs {
     #[arg(long = "config", value_name = "PATH")]
     config: Option<PathBuf>,
 
+    /// Apply the profile NAME of the configuration, also set with TOPGRADE_PROFILE
+    #[arg(long = "profile", value_name = "NAME")]
+    profile: Option<String>,
+
     /// A regular expression for restricting remote host execution
     #[arg(long = "remote-host-limit", value_name = "REGEX")]
     remote_host_limit: Option<Regex>,
@@ -885,6 +1917,25 @@ pub struct CommandLineArgs {
     /// Don't update Topgrade
     #[arg(long = "no-self-update")]
     pub no_self_update: bool,
+
+    /// Write the output of the run to a log file
+    #[arg(long = "log-file")]
+    log_file: bool,
+
+    /// Write a machine-readable report of the run in the given format
+    #[arg(long = "report-format", value_name = "FORMAT", value_enum)]
+    report_format: Option<ReportFormat>,
+
+    /// List the N slowest steps after the summary
+    #[arg(long = "slowest", value_name = "N")]
+    slowest: Option<usize>,
+
+    /// Write the report to the given file instead of stdout
+    #[arg(long = "report-file", value_name = "PATH", requires = "report_format")]
+    report_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    action: Option<Action>,
 }
 
 impl CommandLineArgs {
@@ -892,6 +1943,11 @@ impl CommandLineArgs {
         self.edit_config
     }
 
+    /// The command to run instead of upgrading the system, if any.
+    pub fn action(&self) -> Option<&Action> {
+        self.action.as_ref()
+    }
+
     pub fn show_config_reference(&self) -> bool {
         self.show_config_reference
     }
@@ -932,6 +1988,10 @@ pub struct Config {
     opt: CommandLineArgs,
     config_file: ConfigFile,
     allowed_steps: Vec<Step>,
+    /// Whether parts of the configuration could not be read and were ignored.
+    load_failed: bool,
+    /// The configuration files that were merged, in the order of the merge.
+    sources: Vec<Source>,
 }
 
 impl Config {
@@ -940,27 +2000,242 @@ impl Config {
     /// The function parses the command line arguments and reads the configuration file.
     pub fn load(opt: CommandLineArgs) -> Result<Self> {
         let config_directory = config_directory();
-        let config_file = if config_directory.is_dir() {
-            ConfigFile::read(opt.config.clone()).unwrap_or_else(|e| {
+        let mut sources = Vec::new();
+        // The environment variables win over the profile, the profile over the files, and the
+        // command line over all of them.
+        let (mut config_file, complete) = ConfigFile::from_env(&mut sources);
+        let mut load_failed = !complete;
+        let from_env = sources.len();
+        let mut from_files = if config_directory.is_dir() {
+            let (from_files, complete) = ConfigFile::read(opt.config.clone(), &mut sources).unwrap_or_else(|e| {
                 // Inform the user about errors when loading the configuration,
                 // but fallback to the default config to at least attempt to do something
                 error!("failed to load configuration: {e}");
-                ConfigFile::default()
-            })
+                (ConfigFile::default(), false)
+            });
+            load_failed |= !complete;
+            from_files
         } else {
             debug!("Configuration directory {} does not exist", config_directory.display());
             ConfigFile::default()
         };
 
+        let profile = opt.profile.clone().or_else(|| env::var("TOPGRADE_PROFILE").ok());
+        let mut profile_config = profile.as_deref().and_then(|name| config_file.take_profile(name));
+        inner_merge_opt(
+            &mut profile_config,
+            profile.as_deref().and_then(|name| from_files.take_profile(name)),
+        );
+        // The profiles that are not used are not part of the configuration.
+        config_file.profile = None;
+        from_files.profile = None;
+        if let Some(name) = &profile {
+            if profile_config.is_none() {
+                bail!(t!("There is no profile named {name}", name = name));
+            }
+            debug!("Using the profile {name}");
+            let profile_sources: Vec<_> = sources.iter().filter_map(|source| source.profile(name)).collect();
+            sources.splice(from_env..from_env, profile_sources);
+        }
+        config_file.merge(profile_config.unwrap_or_default());
+        config_file.merge(from_files);
+
+        if opt.strict && load_failed {
+            bail!(t!(
+                "Refusing to run with errors in the configuration, see `topgrade config check`"
+            ));
+        }
+
         let allowed_steps = Self::allowed_steps(&opt, &config_file);
 
         Ok(Self {
             opt,
             config_file,
             allowed_steps,
+            load_failed,
+            sources,
         })
     }
 
+    /// Whether parts of the configuration could not be read, in which case they are ignored.
+    /// A configuration made of the command line alone, without reading any file.
+    #[cfg(test)]
+    pub(crate) fn from_args(opt: CommandLineArgs) -> Self {
+        Self {
+            opt,
+            config_file: ConfigFile::default(),
+            allowed_steps: Vec::new(),
+            load_failed: false,
+            sources: Vec::new(),
+        }
+    }
+
+    pub fn load_failed(&self) -> bool {
+        self.load_failed
+    }
+
+    /// Check the configuration files and print every problem in them. Fails if there is any.
+    pub fn check_files(opt: &CommandLineArgs) -> Result<()> {
+        let mut diagnostics = Vec::new();
+        let files = ConfigFile::check(opt.config.clone(), &mut diagnostics)?;
+        for diagnostic in &diagnostics {
+            println!("{}", style(diagnostic).red());
+        }
+
+        if diagnostics.is_empty() {
+            println!(
+                "{}",
+                t!("No problems found in {count} configuration files", count = files)
+            );
+            Ok(())
+        } else {
+            println!("\n{}", t!("{count} problems found", count = diagnostics.len()));
+            Err(ConfigError.into())
+        }
+    }
+
+    /// Print the configuration merged from every file, with the values that the command line
+    /// arguments override. With `origin`, every value is printed on its own line along with the
+    /// files or the command line it comes from. The secrets are hidden unless `show_secrets` is set.
+    pub fn show(&self, format: ConfigFormat, origin: bool, show_secrets: bool) -> Result<()> {
+        let toml::Value::Table(files) = toml::Value::try_from(&self.config_file)? else {
+            unreachable!("The configuration is serialized as a table");
+        };
+
+        let mut merged = files.clone();
+        let overrides = self.command_line_overrides()?;
+        if !overrides.is_empty() {
+            if let toml::Value::Table(misc) = merged.entry("misc").or_insert(toml::Table::new().into()) {
+                misc.extend(overrides.into_iter().map(|(key, value)| (key.to_string(), value)));
+            }
+        }
+
+        if !origin {
+            if !show_secrets {
+                redact_secrets(&mut Vec::new(), &mut merged);
+            }
+            let output = match format {
+                ConfigFormat::Toml => toml::to_string_pretty(&merged)?,
+                ConfigFormat::Json => serde_json::to_string_pretty(&merged)?,
+            };
+            println!("{}", output.trim_end());
+            return Ok(());
+        }
+
+        let mut values = Vec::new();
+        collect_values(&mut Vec::new(), &merged, &mut values);
+        let values = values.into_iter().map(|(key, value)| {
+            let origins = self.origins(&key, lookup(&files, &key), value);
+            let value = if !show_secrets && is_secret(&key) {
+                "***".into()
+            } else {
+                value.clone()
+            };
+            (
+                key.iter().map(|key| toml_key(key)).collect::<Vec<_>>().join("."),
+                value,
+                origins,
+            )
+        });
+
+        match format {
+            ConfigFormat::Toml => {
+                for (key, value, origins) in values {
+                    println!("{key} = {value}  # {}", origins.join(", "));
+                }
+            }
+            ConfigFormat::Json => {
+                #[derive(Serialize)]
+                struct Annotated {
+                    value: toml::Value,
+                    origin: Vec<String>,
+                }
+
+                let values: IndexMap<_, _> = values
+                    .map(|(key, value, origin)| (key, Annotated { value, origin }))
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&values)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The values of `[misc]` that the command line arguments change, as `config show` prints them.
+    fn command_line_overrides(&self) -> Result<Vec<(&'static str, toml::Value)>> {
+        let misc = self.config_file.misc.as_ref();
+        let mut overrides = Vec::new();
+
+        for (key, from_file, from_command_line) in [
+            ("only", misc.and_then(|misc| misc.only.as_ref()), &self.opt.only),
+            (
+                "disable",
+                misc.and_then(|misc| misc.disable.as_ref()),
+                &self.opt.disable,
+            ),
+        ] {
+            if !from_command_line.is_empty() {
+                let steps: Vec<_> = from_file.into_iter().flatten().chain(from_command_line).collect();
+                overrides.push((key, toml::Value::try_from(steps)?));
+            }
+        }
+
+        for (key, enabled) in [
+            ("no_self_update", self.opt.no_self_update),
+            ("run_in_tmux", self.opt.run_in_tmux),
+            ("cleanup", self.opt.cleanup),
+            ("log_file", self.opt.log_file),
+            ("no_retry", self.opt.no_retry),
+        ] {
+            if enabled {
+                overrides.push((key, true.into()));
+            }
+        }
+
+        if self.opt.skip_notify {
+            overrides.push(("skip_notify", true.into()));
+        }
+        if self.opt.yes.as_ref().is_some_and(Vec::is_empty) {
+            overrides.push(("assume_yes", true.into()));
+        }
+
+        Ok(overrides)
+    }
+
+    /// Where the value at `key` comes from. It is the first file defining it when the value is
+    /// taken from there as is, or else every file defining it, as the value merges them. The
+    /// command line is added when it changed the value of the files, `from_files`.
+    fn origins(&self, key: &[&str], from_files: Option<&toml::Value>, value: &toml::Value) -> Vec<String> {
+        let defined: Vec<_> = self
+            .sources
+            .iter()
+            .filter_map(|source| Some((source.origin.clone(), lookup(&source.table, key)?)))
+            .collect();
+
+        let mut origins: Vec<_> = match defined.first() {
+            Some((path, first)) if Some(*first) == from_files => vec![path.clone()],
+            _ => defined.into_iter().map(|(path, _)| path).collect(),
+        };
+        // The parts of a file split at its `[include]` sections are separate sources.
+        origins.dedup();
+
+        if from_files != Some(value) {
+            origins.push(t!("command line").to_string());
+        }
+        origins
+    }
+
+    /// Migrate the configuration files to the current format. `dry_run` only prints the changes,
+    /// as does `--dry-run` before the command.
+    pub fn migrate_files(opt: &CommandLineArgs, dry_run: bool) -> Result<()> {
+        ConfigFile::migrate(opt.config.clone(), dry_run || opt.dry_run)
+    }
+
+    /// The command to run instead of upgrading the system, if any.
+    pub fn action(&self) -> Option<&Action> {
+        self.opt.action.as_ref()
+    }
+
     /// Launch an editor to edit the configuration
     pub fn edit() -> Result<()> {
         ConfigFile::edit()
@@ -1050,13 +2325,15 @@ impl Config {
 
     /// Tell whether we should run in tmux.
     pub fn run_in_tmux(&self) -> bool {
-        self.opt.run_in_tmux
-            || self
-                .config_file
-                .misc
-                .as_ref()
-                .and_then(|misc| misc.run_in_tmux)
-                .unwrap_or(false)
+        // Attaching to the tmux session needs a terminal
+        !self.unattended()
+            && (self.opt.run_in_tmux
+                || self
+                    .config_file
+                    .misc
+                    .as_ref()
+                    .and_then(|misc| misc.run_in_tmux)
+                    .unwrap_or(false))
     }
 
     /// The preferred way to run the new tmux session.
@@ -1084,6 +2361,145 @@ impl Config {
         self.opt.dry_run
     }
 
+    /// Tell whether nobody is there to answer prompts.
+    pub fn unattended(&self) -> bool {
+        self.opt.unattended
+    }
+
+    /// The configuration file given on the command line, if any.
+    pub fn config_path(&self) -> Option<&Path> {
+        self.opt.config.as_deref()
+    }
+
+    /// Tell whether we only check for available updates.
+    pub fn check(&self) -> bool {
+        self.opt.check
+    }
+
+    /// Tell whether to resume the last run if it was interrupted.
+    pub fn resume(&self) -> bool {
+        self.opt.resume
+    }
+
+    /// Tell whether to only run the steps that failed in the last run.
+    pub fn rerun_failed(&self) -> bool {
+        self.opt.rerun_failed
+    }
+
+    /// Ordering constraints from the configuration. In each pair, the first step has to run before
+    /// the second one.
+    pub fn step_order(&self) -> Vec<(Step, Step)> {
+        let mut order = Vec::new();
+
+        if let Some(steps) = self.config_file.misc.as_ref().and_then(|misc| misc.order.as_ref()) {
+            order.extend(steps.windows(2).map(|pair| (pair[0], pair[1])));
+        }
+
+        for (&step, settings) in self.config_file.step.iter().flatten() {
+            order.extend(settings.after.iter().flatten().map(|&after| (after, step)));
+            order.extend(settings.before.iter().flatten().map(|&before| (step, before)));
+        }
+
+        order
+    }
+
+    /// Whether to write the output of the run to a log file.
+    pub fn log_file(&self) -> bool {
+        self.opt.log_file
+            || self
+                .config_file
+                .misc
+                .as_ref()
+                .and_then(|misc| misc.log_file)
+                .unwrap_or(false)
+    }
+
+    /// How long `step` may run before its commands are killed, if it has a time limit. A timeout
+    /// of zero disables the limit.
+    pub fn step_timeout(&self, step: Step) -> Option<Duration> {
+        self.config_file
+            .step
+            .as_ref()
+            .and_then(|steps| steps.get(&step))
+            .and_then(|settings| settings.timeout)
+            .or_else(|| self.config_file.misc.as_ref().and_then(|misc| misc.step_timeout))
+            .filter(|timeout| !timeout.is_zero())
+    }
+
+    /// When to retry `step` without asking if it fails, if it is retried automatically at all.
+    pub fn auto_retry(&self, step: Step) -> Option<AutoRetry> {
+        let misc = self.config_file.misc.as_ref().and_then(|misc| misc.auto_retry.clone());
+        let mut auto_retry = self
+            .config_file
+            .step
+            .as_ref()
+            .and_then(|steps| steps.get(&step))
+            .and_then(|settings| settings.auto_retry.clone());
+        match (&mut auto_retry, misc) {
+            (Some(auto_retry), Some(misc)) => auto_retry.merge(misc),
+            (None, misc) => auto_retry = misc,
+            _ => (),
+        }
+
+        auto_retry.filter(|auto_retry| auto_retry.attempts() > 1)
+    }
+
+    /// The command to run as the `hook` of `step`, if there is one. Checking for updates runs no
+    /// hooks, as it has no side effects.
+    pub fn hook(&self, step: Step, hook: Hook) -> Option<&str> {
+        if self.check() {
+            return None;
+        }
+        let hooks = self.config_file.hooks.as_ref()?.get(&step)?;
+        match hook {
+            Hook::Pre => hooks.pre.as_deref(),
+            Hook::Post => hooks.post.as_deref(),
+            Hook::OnFailure => hooks.on_failure.as_deref(),
+        }
+    }
+
+    /// Whether to reboot at the end of the run.
+    pub fn auto_reboot(&self) -> AutoReboot {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.auto_reboot)
+            .unwrap_or_default()
+    }
+
+    /// Which log files of past runs to keep.
+    pub fn log_rotation(&self) -> LogRotation {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.log_rotation)
+            .unwrap_or_default()
+    }
+
+    /// Which records of past runs to keep. Unlike the logs, the last 100 runs are kept by default.
+    pub fn history_rotation(&self) -> LogRotation {
+        let rotation = self
+            .config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.history_rotation)
+            .unwrap_or_default();
+        LogRotation {
+            keep: Some(rotation.keep.unwrap_or(100)),
+            ..rotation
+        }
+    }
+
+    /// How many unattended steps may run at the same time.
+    pub fn parallelism(&self) -> usize {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.parallelism)
+            .unwrap_or(1)
+            .max(1)
+    }
+
     /// Tell whether we should not attempt to retry anything.
     pub fn no_retry(&self) -> bool {
         self.opt.no_retry
@@ -1159,11 +2575,13 @@ impl Config {
 
     /// Skip sending a notification at the end of a run
     pub fn skip_notify(&self) -> bool {
-        if let Some(yes) = self.config_file.misc.as_ref().and_then(|misc| misc.skip_notify) {
-            return yes;
-        }
-
         self.opt.skip_notify
+            || self
+                .config_file
+                .misc
+                .as_ref()
+                .and_then(|misc| misc.skip_notify)
+                .unwrap_or(false)
     }
 
     /// Whether to set the terminal title
@@ -1177,19 +2595,17 @@ impl Config {
 
     /// Whether to say yes to package managers
     pub fn yes(&self, step: Step) -> bool {
-        if let Some(yes) = self.config_file.misc.as_ref().and_then(|misc| misc.assume_yes) {
-            return yes;
-        }
-
         if let Some(yes_list) = &self.opt.yes {
-            if yes_list.is_empty() {
+            if yes_list.is_empty() || yes_list.contains(&step) {
                 return true;
             }
-
-            return yes_list.contains(&step);
         }
 
-        false
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.assume_yes)
+            .unwrap_or(false)
     }
 
     /// Bash-it branch
@@ -1309,6 +2725,49 @@ impl Config {
             .unwrap_or_default()
     }
 
+    /// The services to send notifications about runs to.
+    pub fn notifiers(&self) -> Vec<&dyn Notifier> {
+        let Some(notify) = &self.config_file.notify else {
+            return Vec::new();
+        };
+
+        let mut notifiers: Vec<&dyn Notifier> = Vec::new();
+        if let Some(webhook) = &notify.webhook {
+            notifiers.push(webhook);
+        }
+        if let Some(ntfy) = &notify.ntfy {
+            notifiers.push(ntfy);
+        }
+        if let Some(gotify) = &notify.gotify {
+            notifiers.push(gotify);
+        }
+        if let Some(email) = &notify.email {
+            notifiers.push(email);
+        }
+        if let Some(command) = &notify.command {
+            notifiers.push(command);
+        }
+        notifiers
+    }
+
+    /// Whether to only send notifications at the end of runs that failed.
+    pub fn notify_only_on_failure(&self) -> bool {
+        self.config_file
+            .notify
+            .as_ref()
+            .and_then(|notify| notify.only_on_failure)
+            .unwrap_or(false)
+    }
+
+    /// Whether to also send notifications about every failed step, as soon as it failed.
+    pub fn notify_each_failed_step(&self) -> bool {
+        self.config_file
+            .notify
+            .as_ref()
+            .and_then(|notify| notify.each_failed_step)
+            .unwrap_or(false)
+    }
+
     /// Whether to send a desktop notification at the beginning of every step
     pub fn notify_each_step(&self) -> bool {
         self.config_file
@@ -1364,6 +2823,29 @@ impl Config {
             .unwrap_or(true)
     }
 
+    /// The tool taking a filesystem snapshot before the system upgrade, if any
+    pub fn snapshot_tool(&self) -> Option<SnapshotTool> {
+        self.config_file.linux.as_ref().and_then(|s| s.snapshot)
+    }
+
+    /// Whether to take another snapshot after the system upgrade
+    pub fn snapshot_after(&self) -> bool {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|s| s.snapshot_after)
+            .unwrap_or(false)
+    }
+
+    /// The directory the btrfs snapshots are created in
+    pub fn btrfs_snapshot_dir(&self) -> &Path {
+        self.config_file
+            .linux
+            .as_ref()
+            .and_then(|s| s.btrfs_snapshot_dir.as_deref())
+            .unwrap_or(Path::new("/.snapshots"))
+    }
+
     /// Get the package manager of an Arch Linux system
     pub fn arch_package_manager(&self) -> ArchPackageManager {
         self.config_file
@@ -1532,6 +3014,16 @@ impl Config {
             .is_some_and(|v| v.contains(&step))
     }
 
+    /// Whether the failures of the steps in `ignore_failures` make the run fail anyway, even
+    /// though they do not stop it.
+    pub fn count_ignored_failures(&self) -> bool {
+        self.config_file
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.count_ignored_failures)
+            .unwrap_or(false)
+    }
+
     pub fn use_predefined_git_repos(&self) -> bool {
         !self.opt.disable_predefined_git_repos
             && self
@@ -1571,6 +3063,21 @@ impl Config {
         self.opt.show_skipped
     }
 
+    /// How many of the slowest steps to list after the summary
+    pub fn slowest(&self) -> Option<usize> {
+        self.opt.slowest
+    }
+
+    /// The format of the machine-readable report, if one was requested
+    pub fn report_format(&self) -> Option<ReportFormat> {
+        self.opt.report_format
+    }
+
+    /// Where to write the machine-readable report, `None` meaning stdout
+    pub fn report_file(&self) -> Option<&Path> {
+        self.opt.report_file.as_deref()
+    }
+
     pub fn open_remotes_in_new_terminal(&self) -> bool {
         self.config_file
             .windows
@@ -1786,14 +3293,278 @@ mod test {
         assert!(toml::from_str::<ConfigFile>(str).is_ok());
     }
 
-    fn config() -> Config {
-        Config {
-            opt: CommandLineArgs::parse_from::<_, String>([]),
-            config_file: ConfigFile::default(),
-            allowed_steps: Vec::new(),
+    #[test]
+    fn test_step_conflicts() {
+        assert!(Step::Node.conflicts_with(Step::Yarn));
+        assert!(Step::Yarn.conflicts_with(Step::Node));
+        assert!(Step::Go.conflicts_with(Step::Go));
+        assert!(!Step::Cargo.conflicts_with(Step::Pipx));
+    }
+
+    #[test]
+    fn test_log_rotation() {
+        let config: ConfigFile =
+            toml::from_str("[misc]\nlog_rotation = { max_age = \"1d12h\", max_size = 2 }").unwrap();
+        let rotation = config.misc.unwrap().log_rotation.unwrap();
+
+        assert_eq!(rotation.keep(), 10);
+        assert_eq!(rotation.max_age(), Some(Duration::from_secs(36 * 60 * 60)));
+        assert_eq!(rotation.max_size(), Some(2 * 1024 * 1024));
+        assert!(toml::from_str::<ConfigFile>("[misc]\nlog_rotation = { max_age = \"1 week\" }").is_err());
+    }
+
+    #[test]
+    fn test_history_rotation() {
+        let config = Config {
+            config_file: toml::from_str("[misc]\nhistory_rotation = { max_age = \"365d\" }").unwrap(),
+            ..config()
+        };
+        assert_eq!(config.history_rotation().keep(), 100);
+        assert_eq!(config.log_rotation().keep(), 10);
+    }
+
+    #[test]
+    fn test_step_timeout() {
+        let config = Config {
+            config_file: toml::from_str(
+                "[misc]\nstep_timeout = \"30m\"\n[step.gcloud]\ntimeout = \"2h\"\n[step.cargo]\ntimeout = \"0\"",
+            )
+            .unwrap(),
+            ..config()
+        };
+
+        assert_eq!(
+            config.step_timeout(Step::Gcloud),
+            Some(Duration::from_secs(2 * 60 * 60))
+        );
+        assert_eq!(config.step_timeout(Step::Pipx), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(config.step_timeout(Step::Cargo), None);
+    }
+
+    #[test]
+    fn test_auto_retry() {
+        let config = Config {
+            config_file: toml::from_str(
+                "[misc]\nauto_retry = { attempts = 3, backoff = \"10s\" }\n\
+                 [step.cargo]\nauto_retry = { patterns = [\"crates.io\"] }\n\
+                 [step.pipx]\nauto_retry = { attempts = 1 }",
+            )
+            .unwrap(),
+            ..config()
+        };
+
+        let auto_retry = config.auto_retry(Step::Gcloud).unwrap();
+        assert_eq!(auto_retry.attempts(), 3);
+        assert_eq!(auto_retry.backoff(2), Duration::from_secs(10));
+        assert_eq!(auto_retry.backoff(3), Duration::from_secs(20));
+        assert!(auto_retry.is_transient("curl: (6) Could not resolve host: example.com"));
+        assert!(auto_retry.is_transient("The requested URL returned error: 503"));
+        assert!(!auto_retry.is_transient("error: 5032 packages"));
+
+        let auto_retry = config.auto_retry(Step::Cargo).unwrap();
+        assert_eq!(auto_retry.backoff(2), Duration::from_secs(10));
+        assert!(auto_retry.is_transient("failed to download from crates.io"));
+        assert!(!auto_retry.is_transient("Could not resolve host"));
+
+        assert!(config.auto_retry(Step::Pipx).is_none());
+        assert!(toml::from_str::<ConfigFile>("[misc]\nauto_retry = { patterns = [\"(\"] }").is_err());
+    }
+
+    #[test]
+    fn test_hooks() {
+        let config = Config {
+            config_file: toml::from_str(
+                "[hooks.system]\npre = \"snapper create\"\n\
+                 [hooks.cargo]\non_failure = \"echo $TOPGRADE_STEP_RESULT\"",
+            )
+            .unwrap(),
+            ..config()
+        };
+
+        assert_eq!(config.hook(Step::System, Hook::Pre), Some("snapper create"));
+        assert_eq!(config.hook(Step::System, Hook::Post), None);
+        assert_eq!(
+            config.hook(Step::Cargo, Hook::OnFailure),
+            Some("echo $TOPGRADE_STEP_RESULT")
+        );
+        assert_eq!(config.hook(Step::Pipx, Hook::Pre), None);
+        assert!(toml::from_str::<ConfigFile>("[hooks.cargo]\nbefore = \"true\"").is_err());
+    }
+
+    #[test]
+    fn test_no_hooks_when_checking() {
+        let config = Config {
+            opt: CommandLineArgs::parse_from(["topgrade", "--check"]),
+            config_file: toml::from_str("[hooks.system]\npre = \"snapper create\"\npost = \"true\"").unwrap(),
+            ..config()
+        };
+
+        for hook in [Hook::Pre, Hook::Post, Hook::OnFailure] {
+            assert_eq!(config.hook(Step::System, hook), None);
+        }
+    }
+
+    #[test]
+    fn test_split_includes() {
+        let contents = "version = 2\n\n[include]\npaths = [\"a.toml\"]\n\n[misc]\n  [include]\npaths = [\"b.toml\"]\n";
+        assert_eq!(
+            split_includes(contents),
+            [
+                "version = 2\n",
+                "\n[include]\npaths = [\"a.toml\"]\n\n[misc]\n",
+                "  [include]\npaths = [\"b.toml\"]\n"
+            ]
+        );
+        assert_eq!(split_includes("[misc]\n"), ["[misc]\n"]);
+    }
+
+    #[test]
+    fn test_check_reports_every_error() {
+        let source = "[misc]\ndisable = [\"system\", \"nonexistent\"]\nassume_yess = true\n\n[foo]\nbar = 1\n\n[linux]\nsnapshot = \"lvm\"\n";
+        let mut diagnostics = Vec::new();
+        Diagnostic::check_source(Path::new("topgrade.toml"), source, 0, source, &mut diagnostics);
+
+        let reported: Vec<_> = diagnostics.iter().map(|d| (d.position, d.message.as_str())).collect();
+        assert_eq!(reported.len(), 4);
+        assert_eq!(reported[0], (Some((2, 22)), "unknown variant `nonexistent`"));
+        assert_eq!(reported[1].0, Some((3, 1)));
+        assert!(reported[1]
+            .1
+            .starts_with("unknown field `assume_yess`, expected one of `pre_sudo`"));
+        assert_eq!(reported[2].0, Some((5, 2)));
+        assert!(reported[2].1.starts_with("unknown field `foo`"));
+        assert_eq!(
+            reported[3],
+            (
+                Some((9, 12)),
+                "unknown variant `lvm`, expected one of `snapper`, `btrfs`, `timeshift`, `zfs`"
+            )
+        );
+
+        let mut diagnostics = Vec::new();
+        Diagnostic::check_source(Path::new("topgrade.toml"), "[misc\n", 0, "[misc\n", &mut diagnostics);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].position, Some((1, 6)));
+    }
+
+    #[test]
+    fn test_origins() {
+        let included = "[misc]\ndisable = [\"rustup\"]\n[linux]\nsnapshot = \"snapper\"\n";
+        let main = "[misc]\ndisable = [\"system\"]\n[linux]\nsnapshot = \"btrfs\"\n";
+        let mut config_file: ConfigFile = toml::from_str(included).unwrap();
+        config_file.merge(toml::from_str(main).unwrap());
+        let config = Config {
+            opt: CommandLineArgs::parse_from(["topgrade", "--cleanup"]),
+            config_file,
+            sources: vec![
+                Source::new(Path::new("included.toml"), included).unwrap(),
+                Source::new(Path::new("topgrade.toml"), main).unwrap(),
+            ],
+            ..config()
+        };
+
+        let toml::Value::Table(files) = toml::Value::try_from(&config.config_file).unwrap() else {
+            panic!("The configuration is not a table");
+        };
+        let origins = |key: &[&str], value: toml::Value| config.origins(key, lookup(&files, key), &value);
+
+        assert_eq!(origins(&["linux", "snapshot"], "snapper".into()), ["included.toml"]);
+        assert_eq!(
+            origins(
+                &["misc", "disable"],
+                toml::Value::try_from(["system", "rustup"]).unwrap()
+            ),
+            ["included.toml", "topgrade.toml"]
+        );
+        assert_eq!(origins(&["misc", "cleanup"], true.into()), ["command line"]);
+        assert_eq!(
+            config.command_line_overrides().unwrap(),
+            [("cleanup", toml::Value::Boolean(true))]
+        );
+    }
+
+    #[test]
+    fn test_redact_secrets() {
+        let mut table: toml::Table = toml::from_str(
+            r#"
+            [notify.ntfy]
+            url = "https://ntfy.sh/secret"
+            priority = "high"
+            [profile.work.notify.webhook]
+            headers = { Authorization = "Bearer secret" }
+            "#,
+        )
+        .unwrap();
+        redact_secrets(&mut Vec::new(), &mut table);
+        assert_eq!(
+            table,
+            toml::from_str(
+                r#"
+                [notify.ntfy]
+                url = "***"
+                priority = "high"
+                [profile.work.notify.webhook]
+                headers = { Authorization = "***" }
+                "#
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_env_config() {
+        let (config_file, table) = env_config("TOPGRADE__MISC__NO_RETRY", "true").unwrap();
+        assert_eq!(config_file.misc.unwrap().no_retry, Some(true));
+        assert_eq!(table.to_string(), "[misc]\nno_retry = true\n");
+
+        let (config_file, _) = env_config("TOPGRADE__MISC__DISABLE", "[\"cargo\", \"system\"]").unwrap();
+        assert_eq!(config_file.misc.unwrap().disable, Some(vec![Step::Cargo, Step::System]));
+
+        // Values that are not valid TOML, or not of the right type, are strings.
+        let (config_file, _) = env_config("TOPGRADE__LINUX__APT_ARGUMENTS", "--no-install-recommends").unwrap();
+        assert_eq!(
+            config_file.linux.unwrap().apt_arguments.as_deref(),
+            Some("--no-install-recommends")
+        );
+        let (config_file, _) = env_config("TOPGRADE__MISC__BASHIT_BRANCH", "1").unwrap();
+        assert_eq!(config_file.misc.unwrap().bashit_branch.as_deref(), Some("1"));
+
+        assert!(env_config("TOPGRADE__MISC__NO_RETRY", "maybe").is_err());
+        assert!(env_config("TOPGRADE__MISC__NOPE", "true").is_err());
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut config_file: ConfigFile = toml::from_str(
+            "[misc]\ndisable = [\"system\"]\nassume_yes = true\n\
+             [profile.quick.misc]\ndisable = [\"containers\"]\nassume_yes = false\n\
+             [profile.quick.linux]\napt_arguments = \"-q\"\n\
+             [profile.server.misc]\nno_self_update = true\n",
+        )
+        .unwrap();
+
+        let mut profile = config_file.take_profile("quick").unwrap();
+        assert!(config_file.profile.is_none());
+        profile.merge(config_file);
+        let misc = profile.misc.unwrap();
+        assert_eq!(misc.disable, Some(vec![Step::System, Step::Containers]));
+        assert_eq!(misc.assume_yes, Some(false));
+        assert_eq!(misc.no_self_update, None);
+        assert_eq!(profile.linux.unwrap().apt_arguments.as_deref(), Some("-q"));
+
+        for contents in [
+            "[profile.quick.include]\npaths = [\"quick.toml\"]\n",
+            "[profile.quick.profile.nested.misc]\nassume_yes = true\n",
+            "[profile.quick]\nversion = 2\n",
+        ] {
+            assert!(toml::from_str::<ConfigFile>(contents).is_err());
         }
     }
 
+    fn config() -> Config {
+        Config::from_args(CommandLineArgs::parse_from::<_, String>([]))
+    }
+
     #[test]
     fn test_should_execute_remote_different_hostname() {
         assert!(config().should_execute_remote(Ok("hostname".to_string()), "remote_hostname"));
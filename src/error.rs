@@ -1,8 +1,10 @@
-use std::{fmt::Display, process::ExitStatus};
+use std::{fmt::Display, process::ExitStatus, time::Duration};
 
 use rust_i18n::t;
 use thiserror::Error;
 
+use crate::utils::format_duration;
+
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum TopgradeError {
     ProcessFailed(String, ExitStatus),
@@ -68,6 +70,46 @@ impl Display for StepFailed {
     }
 }
 
+#[derive(Error, Debug)]
+pub struct ConfigError;
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", t!("Failed to load the configuration"))
+    }
+}
+
+#[derive(Error, Debug)]
+pub struct UpdatesAvailable;
+
+impl Display for UpdatesAvailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", t!("Updates are available"))
+    }
+}
+
+#[derive(Error, Debug)]
+pub struct RebootRequired;
+
+impl Display for RebootRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", t!("A reboot is required"))
+    }
+}
+
+#[derive(Error, Debug)]
+pub struct StepTimedOut(pub Duration);
+
+impl Display for StepTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            t!("The step timed out after {timeout}", timeout = format_duration(self.0))
+        )
+    }
+}
+
 #[derive(Error, Debug)]
 pub struct DryRun();
 
@@ -1,8 +1,10 @@
 #![allow(dead_code)]
 use crate::executor::RunType;
+use crate::history;
 use crate::sudo::Sudo;
 use crate::utils::{get_require_sudo_string, require_option};
 use crate::{config::Config, executor::Executor};
+use chrono::{DateTime, Local};
 use color_eyre::eyre::Result;
 use std::env::var;
 use std::path::Path;
@@ -18,6 +20,8 @@ pub struct ExecutionContext<'a> {
     tmux_session: Mutex<Option<String>>,
     /// True if topgrade is running under ssh.
     under_ssh: bool,
+    /// When the run started, which its ID is derived from.
+    started_at: DateTime<Local>,
 }
 
 impl<'a> ExecutionContext<'a> {
@@ -29,6 +33,7 @@ impl<'a> ExecutionContext<'a> {
             config,
             tmux_session: Mutex::new(None),
             under_ssh,
+            started_at: Local::now(),
         }
     }
 
@@ -53,6 +58,15 @@ impl<'a> ExecutionContext<'a> {
         self.under_ssh
     }
 
+    pub fn started_at(&self) -> DateTime<Local> {
+        self.started_at
+    }
+
+    /// The ID of the run, as listed by `topgrade history`.
+    pub fn run_id(&self) -> String {
+        history::run_id(self.started_at)
+    }
+
     pub fn set_tmux_session(&self, session_name: String) {
         self.tmux_session.lock().unwrap().replace(session_name);
     }
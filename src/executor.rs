@@ -1,14 +1,16 @@
 //! Utilities for command execution
 use std::ffi::{OsStr, OsString};
 use std::path::Path;
-use std::process::{Child, Command, ExitStatus, Output};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
 
 use color_eyre::eyre::Result;
 use rust_i18n::t;
 use tracing::debug;
 
-use crate::command::CommandExt;
+use crate::command::{self, CommandExt};
 use crate::error::DryRun;
+use crate::log_file;
+use crate::terminal::{is_unattended, write_captured};
 
 /// An enum telling whether Topgrade should perform dry runs or actually perform the steps.
 #[derive(Clone, Copy, Debug)]
@@ -152,6 +154,9 @@ impl Executor {
         let result = match self {
             Executor::Wet(c) => {
                 debug!("Running {:?}", c);
+                if is_unattended() {
+                    c.stdin(Stdio::null());
+                }
                 // We should use `spawn()` here rather than `spawn_checked()` since
                 // their semantics and behaviors are different.
                 #[allow(clippy::disallowed_methods)]
@@ -172,8 +177,7 @@ impl Executor {
             Executor::Wet(c) => {
                 // We should use `output()` here rather than `output_checked()` since
                 // their semantics and behaviors are different.
-                #[allow(clippy::disallowed_methods)]
-                Ok(ExecutorOutput::Wet(c.output()?))
+                Ok(ExecutorOutput::Wet(command::output(c)?))
             }
             Executor::Dry(c) => {
                 c.dry_run();
@@ -217,23 +221,26 @@ pub struct DryCommand {
 
 impl DryCommand {
     fn dry_run(&self) {
-        print!(
-            "{}",
-            t!(
-                "Dry running: {program_name} {arguments}",
-                program_name = self.program.to_string_lossy(),
-                arguments = shell_words::join(
-                    self.args
-                        .iter()
-                        .map(|a| String::from(a.to_string_lossy()))
-                        .collect::<Vec<String>>()
-                )
+        let mut message = t!(
+            "Dry running: {program_name} {arguments}",
+            program_name = self.program.to_string_lossy(),
+            arguments = shell_words::join(
+                self.args
+                    .iter()
+                    .map(|a| String::from(a.to_string_lossy()))
+                    .collect::<Vec<String>>()
             )
-        );
-        match &self.directory {
-            Some(dir) => println!(" {}", t!("in {directory}", directory = dir.to_string_lossy())),
-            None => println!(),
-        };
+        )
+        .to_string();
+        if let Some(dir) = &self.directory {
+            message.push_str(&format!(" {}", t!("in {directory}", directory = dir.to_string_lossy())));
+        }
+        message.push('\n');
+
+        if !write_captured(&message) {
+            log_file::write(&message);
+            print!("{message}");
+        }
     }
 }
 
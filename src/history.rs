@@ -0,0 +1,438 @@
+//! Records of past runs, kept under the data directory so that they can be looked up after the
+//! fact, e.g. to find out which packages changed on a given day when something breaks.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, Local};
+use color_eyre::eyre::{eyre, Context, Result};
+use console::style;
+use rust_i18n::t;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::config::{LogRotation, Step};
+use crate::log_file;
+use crate::report::{PackageChange, Report, Snapshot, StepResult};
+use crate::terminal::print_result;
+use crate::utils::{data_dir, format_duration, hostname, rotate_files};
+
+/// The directory holding the history of Topgrade.
+pub fn history_dir() -> PathBuf {
+    data_dir().join("topgrade")
+}
+
+/// The directory of the run records, with the JSON report of each run.
+fn runs_dir() -> PathBuf {
+    history_dir().join("runs")
+}
+
+/// The ID of the run started at `started_at`, which sorts like the start times. The milliseconds
+/// tell apart the runs started within the same second.
+pub fn run_id(started_at: DateTime<Local>) -> String {
+    started_at.format("%Y%m%d-%H%M%S-%3f").to_string()
+}
+
+/// The package log, with one JSON object per changed package.
+fn packages_path() -> PathBuf {
+    history_dir().join("packages.jsonl")
+}
+
+/// A package changed by a past run.
+#[derive(Serialize)]
+struct PackageRecord<'r> {
+    time: String,
+    hostname: Option<&'r str>,
+    step: String,
+    key: &'r str,
+    #[serde(flatten)]
+    change: &'r PackageChange,
+}
+
+/// Append the packages changed during the run of `report` to the package log.
+pub fn append_package_changes(report: &Report) -> Result<()> {
+    let hostname = hostname().ok();
+    let mut lines = String::new();
+    for r in report.data() {
+        for change in &r.packages {
+            let record = PackageRecord {
+                time: r.finished_at.to_rfc3339(),
+                hostname: hostname.as_deref(),
+                step: r.step.to_string(),
+                key: &r.key,
+                change,
+            };
+            lines.push_str(&serde_json::to_string(&record)?);
+            lines.push('\n');
+        }
+    }
+
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    let path = packages_path();
+    fs::create_dir_all(history_dir())?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .with_context(|| format!("Failed to write the package log {}", path.display()))
+}
+
+/// A step identified by its key, e.g. a single custom command.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StepKey {
+    pub step: Step,
+    pub key: String,
+}
+
+/// Save the report of the current run in the run records, then remove the records of past runs
+/// according to `rotation`, and the package changes older than the records left. The records of
+/// the runs that took snapshots are kept, so that the snapshots can still be listed.
+pub fn save_run(report: &Report, rotation: LogRotation) -> Result<()> {
+    let path = write_run(report, report.to_json(Local::now())?)?;
+    rotate_files(&runs_dir(), "json", &path, rotation, took_snapshots)?;
+
+    let Some(oldest) = load_runs()?.into_iter().next() else {
+        return Ok(());
+    };
+    let Ok(since) = DateTime::parse_from_rfc3339(&oldest.started_at) else {
+        return Ok(());
+    };
+    let path = packages_path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(kept) = changes_since(&contents, since) {
+        debug!(
+            "Removing the package changes older than {since} from {}",
+            path.display()
+        );
+        fs::write(&path, kept).with_context(|| format!("Failed to write the package log {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Whether the run record at `path` lists snapshots.
+fn took_snapshots(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<Run>(&contents).ok())
+        .is_some_and(|run| run.steps.iter().any(|step| !step.snapshots.is_empty()))
+}
+
+/// The lines of the package log `contents` recorded at or after `since`, or `None` if there is
+/// none to remove. Lines whose time cannot be read are kept.
+fn changes_since(contents: &str, since: DateTime<FixedOffset>) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Line {
+        time: String,
+    }
+
+    let recent = |line: &&str| {
+        serde_json::from_str::<Line>(line)
+            .ok()
+            .and_then(|line| DateTime::parse_from_rfc3339(&line.time).ok())
+            .is_none_or(|time| time >= since)
+    };
+    let kept: Vec<_> = contents.lines().filter(recent).collect();
+    (kept.len() < contents.lines().count()).then(|| kept.iter().flat_map(|line| [line, "\n"]).collect())
+}
+
+/// Save the report of the current run so far, marked as incomplete, together with the steps done
+/// by the interrupted run it resumes. The record is replaced once the run completes.
+pub fn save_progress(report: &Report, resumed: &[StepKey]) -> Result<()> {
+    let mut record: serde_json::Value = serde_json::from_str(&report.to_json(Local::now())?)?;
+    record["incomplete"] = true.into();
+    record["resumed"] = serde_json::to_value(resumed)?;
+    write_run(report, serde_json::to_string_pretty(&record)?)?;
+    Ok(())
+}
+
+/// Write the record of the run of `report`, returning its path.
+fn write_run(report: &Report, record: String) -> Result<PathBuf> {
+    let path = runs_dir().join(format!("{}.json", run_id(report.started_at())));
+    fs::create_dir_all(runs_dir())?;
+    fs::write(&path, record).with_context(|| format!("Failed to write the run record {}", path.display()))?;
+    Ok(path)
+}
+
+/// The ID of the last run and the steps that failed in it, if there was a run.
+pub fn last_failures() -> Result<Option<(String, Vec<StepKey>)>> {
+    let Some(run) = load_runs()?.pop() else {
+        return Ok(None);
+    };
+
+    let failed = run
+        .steps
+        .into_iter()
+        .filter(|step| step.result().failed())
+        .filter_map(|step| {
+            Some(StepKey {
+                step: step.step.parse().ok()?,
+                key: step.key,
+            })
+        })
+        .collect();
+    Ok(Some((run.id, failed)))
+}
+
+/// The ID of the last run and the steps that it completed, if it was interrupted. The steps it
+/// skipped because they were done by the run it resumed count as completed as well.
+pub fn interrupted_run() -> Result<Option<(String, Vec<StepKey>)>> {
+    let Some(run) = load_runs()?.pop().filter(|run| run.incomplete) else {
+        return Ok(None);
+    };
+
+    let mut done = run.resumed;
+    done.extend(
+        run.steps
+            .into_iter()
+            .filter(|step| step.result == "success")
+            .filter_map(|step| {
+                Some(StepKey {
+                    step: step.step.parse().ok()?,
+                    key: step.key,
+                })
+            }),
+    );
+    Ok(Some((run.id, done)))
+}
+
+/// A past run, read back from its JSON report.
+#[derive(Deserialize)]
+struct Run {
+    #[serde(skip)]
+    id: String,
+    hostname: Option<String>,
+    started_at: String,
+    duration: f64,
+    steps: Vec<RunStep>,
+    #[serde(default)]
+    incomplete: bool,
+    #[serde(default)]
+    resumed: Vec<StepKey>,
+}
+
+#[derive(Deserialize)]
+struct RunStep {
+    key: String,
+    step: String,
+    result: String,
+    skip_reason: Option<String>,
+    errors: Vec<String>,
+    packages: Vec<PackageChange>,
+    // Runs recorded before retries were counted do not have it.
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default)]
+    snapshots: Vec<Snapshot>,
+    duration: f64,
+}
+
+impl RunStep {
+    fn result(&self) -> StepResult {
+        StepResult::from_name(&self.result, self.skip_reason.clone())
+            .unwrap_or_else(|| StepResult::Skipped(self.result.clone()))
+    }
+}
+
+/// Read the run records, oldest first.
+fn load_runs() -> Result<Vec<Run>> {
+    let entries = match fs::read_dir(runs_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+        .collect();
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let run = fs::read_to_string(&path)
+                .map_err(|e| eyre!(e))
+                .and_then(|contents| Ok(serde_json::from_str::<Run>(&contents)?));
+            match run {
+                Ok(run) => Some(Run {
+                    id: path.file_stem()?.to_string_lossy().into_owned(),
+                    ..run
+                }),
+                Err(e) => {
+                    debug!("Ignoring the run record {}: {e:?}", path.display());
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+fn seconds(duration: f64) -> Duration {
+    Duration::try_from_secs_f64(duration).unwrap_or_default()
+}
+
+fn local_time(time: &str) -> String {
+    DateTime::parse_from_rfc3339(time).map_or_else(
+        |_| time.to_string(),
+        |time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+    )
+}
+
+/// List the past runs, optionally only the last `last` ones, those in which `step` ran, or those
+/// in which a step (or `step`) failed.
+pub fn print_runs(last: Option<usize>, step: Option<Step>, failed: bool) -> Result<()> {
+    let step = step.map(|step| step.to_string());
+    let runs: Vec<_> = load_runs()?
+        .into_iter()
+        .filter_map(|run| {
+            let steps: Vec<_> = run
+                .steps
+                .iter()
+                .filter(|s| step.as_ref().is_none_or(|step| s.step == *step))
+                .map(RunStep::result)
+                .collect();
+            let keep = !steps.is_empty() && (!failed || steps.iter().any(StepResult::failed));
+            keep.then_some((run, steps))
+        })
+        .collect();
+
+    if runs.is_empty() {
+        println!("{}", t!("No runs found"));
+        return Ok(());
+    }
+
+    let skip = last.map_or(0, |last| runs.len().saturating_sub(last));
+    for (run, results) in runs.iter().skip(skip) {
+        let count = |f: fn(&StepResult) -> bool| results.iter().filter(|r| f(r)).count();
+        let failures = count(StepResult::failed);
+        let mut summary = format!(
+            "{} {}, {} {}, {} {}",
+            count(|r| matches!(r, StepResult::Success)),
+            t!("OK"),
+            failures,
+            t!("FAILED"),
+            count(|r| matches!(r, StepResult::Ignored | StepResult::Skipped(_))),
+            t!("SKIPPED"),
+        );
+        if run.incomplete {
+            summary.push_str(&format!(", {}", t!("INTERRUPTED")));
+        }
+
+        println!(
+            "{}  {}  {}  {:>8}  {}",
+            style(&run.id).bold(),
+            local_time(&run.started_at),
+            run.hostname.as_deref().unwrap_or("-"),
+            format_duration(seconds(run.duration)),
+            if failures > 0 {
+                style(summary).red()
+            } else {
+                style(summary).green()
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Show the results, errors and package changes of every step of the run `id`.
+pub fn print_run(id: &str) -> Result<()> {
+    let run = load_runs()?
+        .into_iter()
+        .find(|run| run.id == id)
+        .ok_or_else(|| eyre!(t!("No run with ID {id}", id = id)))?;
+
+    println!("{}", style(t!("Run {id}", id = run.id)).bold());
+    println!("{}: {}", t!("Started"), local_time(&run.started_at));
+    if let Some(hostname) = &run.hostname {
+        println!("{}: {hostname}", t!("Host"));
+    }
+    println!("{}: {}\n", t!("Duration"), format_duration(seconds(run.duration)));
+
+    for step in &run.steps {
+        let result = step.result();
+        let duration = (!matches!(result, StepResult::Skipped(_))).then(|| seconds(step.duration));
+        print_result(&step.key, &result, duration);
+        if step.attempts > 1 {
+            println!("    {}", t!("Ran {count} times", count = step.attempts));
+        }
+        for error in &step.errors {
+            println!("    {}", style(error).red());
+        }
+        for package in &step.packages {
+            println!("    {package}");
+        }
+        for snapshot in &step.snapshots {
+            println!("    {}", t!("Snapshot {snapshot}", snapshot = snapshot));
+        }
+    }
+
+    if let Ok(log) = fs::read_to_string(log_file::path(&run.id)) {
+        println!("\n{}", style(t!("Log")).bold());
+        print!("{log}");
+    }
+
+    Ok(())
+}
+
+/// List the snapshots taken by the past runs, oldest first, with the command rolling back to each.
+pub fn print_snapshots() -> Result<()> {
+    let runs = load_runs()?;
+    let snapshots: Vec<_> = runs
+        .iter()
+        .flat_map(|run| {
+            run.steps
+                .iter()
+                .flat_map(move |step| step.snapshots.iter().map(move |s| (run, s)))
+        })
+        .collect();
+
+    if snapshots.is_empty() {
+        println!("{}", t!("No snapshots found"));
+        return Ok(());
+    }
+
+    for (run, snapshot) in snapshots {
+        println!(
+            "{}  {}  {}",
+            style(&run.id).bold(),
+            local_time(&run.started_at),
+            snapshot
+        );
+        match snapshot.rollback_command() {
+            Some(command) => println!("    {}", t!("Roll back with: {command}", command = command)),
+            None => println!("    {}", t!("Roll back by replacing the subvolume with the snapshot")),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_changes_since() {
+        let contents = "{\"time\":\"2026-01-01T10:00:00+01:00\",\"name\":\"old\"}\n\
+            {\"time\":\"2026-03-01T10:00:00+01:00\",\"name\":\"new\"}\n\
+            not json\n";
+        let since = |time| DateTime::parse_from_rfc3339(time).unwrap();
+
+        assert_eq!(
+            changes_since(contents, since("2026-02-01T00:00:00Z")).as_deref(),
+            Some("{\"time\":\"2026-03-01T10:00:00+01:00\",\"name\":\"new\"}\nnot json\n")
+        );
+        assert_eq!(changes_since(contents, since("2025-12-01T00:00:00Z")), None);
+    }
+}
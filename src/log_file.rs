@@ -0,0 +1,81 @@
+//! The log file of the current run, which receives a copy of everything Topgrade and the commands
+//! it runs print, so that unattended runs can be inspected afterwards.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use color_eyre::eyre::{Context, Result};
+use console::strip_ansi_codes;
+use tracing::debug;
+
+use crate::config::LogRotation;
+use crate::utils::{rotate_files, state_dir};
+
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+fn logs_dir() -> PathBuf {
+    state_dir().join("topgrade").join("logs")
+}
+
+/// The path of the log file of the run `id`.
+pub fn path(id: &str) -> PathBuf {
+    logs_dir().join(format!("{id}.log"))
+}
+
+/// Start logging to the log file of the run `id`, then remove the logs of past runs according to
+/// `rotation`.
+pub fn start(id: &str, rotation: LogRotation) -> Result<PathBuf> {
+    let path = path(id);
+    fs::create_dir_all(logs_dir())?;
+    let file = File::create(&path).with_context(|| format!("Failed to create the log file {}", path.display()))?;
+    *LOG_FILE.lock().unwrap() = Some(file);
+
+    rotate_files(&logs_dir(), "log", &path, rotation, |_| false)?;
+    Ok(path)
+}
+
+/// Tells whether the output is being logged.
+pub fn is_enabled() -> bool {
+    LOG_FILE.lock().unwrap().is_some()
+}
+
+/// Append `output` to the log file, without its colors and styles.
+pub fn write(output: &str) {
+    if is_enabled() {
+        append(strip_ansi_codes(output).as_bytes());
+    }
+}
+
+fn append(output: &[u8]) {
+    if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+        if let Err(e) = file.write_all(output) {
+            debug!("Failed to write to the log file: {e}");
+        }
+    }
+}
+
+/// Copy everything `reader` produces to `writer` as it comes, and to the log file line by line, so
+/// that the colors and styles can be removed from it as `write` does.
+pub fn tee(mut reader: impl Read, mut writer: impl Write) {
+    let mut buffer = [0; 8192];
+    let mut line = Vec::new();
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                writer.write_all(&buffer[..n]).ok();
+                writer.flush().ok();
+                line.extend_from_slice(&buffer[..n]);
+                if let Some(end) = line.iter().rposition(|&byte| byte == b'\n') {
+                    write(&String::from_utf8_lossy(&line[..=end]));
+                    line.drain(..=end);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(_) => break,
+        }
+    }
+    write(&String::from_utf8_lossy(&line));
+}
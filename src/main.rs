@@ -21,16 +21,17 @@ use once_cell::sync::Lazy;
 use rust_i18n::{i18n, t};
 use tracing::debug;
 
-use self::config::{CommandLineArgs, Config, Step};
-use self::error::StepFailed;
+use self::config::{Action, AutoReboot, CommandLineArgs, Config, ConfigCommand, HistoryCommand, ScheduleCommand, Step};
 #[cfg(all(windows, feature = "self-update"))]
 use self::error::Upgraded;
+use self::error::{ConfigError, RebootRequired, StepFailed, UpdatesAvailable};
+use self::report::StepResult;
 #[allow(clippy::wildcard_imports)]
 use self::steps::{remote::*, *};
 #[allow(clippy::wildcard_imports)]
 use self::terminal::*;
 
-use self::utils::{hostname, install_color_eyre, install_tracing, update_tracing};
+use self::utils::{format_duration, hostname, install_color_eyre, install_tracing, update_tracing};
 
 mod breaking_changes;
 mod command;
@@ -39,8 +40,14 @@ mod ctrlc;
 mod error;
 mod execution_context;
 mod executor;
+mod history;
+mod log_file;
+mod migration;
+mod notify;
+mod registry;
 mod report;
 mod runner;
+mod schedule;
 #[cfg(windows)]
 mod self_renamer;
 #[cfg(feature = "self-update")]
@@ -113,11 +120,23 @@ fn run() -> Result<()> {
         return Ok(());
     }
 
-    let config = Config::load(opt)?;
+    // Checking and migrating the configuration must not depend on loading it.
+    match opt.action() {
+        Some(Action::Config {
+            command: ConfigCommand::Check,
+        }) => return Config::check_files(&opt),
+        Some(Action::Config {
+            command: ConfigCommand::Migrate { dry_run },
+        }) => return Config::migrate_files(&opt, *dry_run),
+        _ => (),
+    }
+
+    let config = Config::load(opt).wrap_err(ConfigError)?;
     // Update the logger with the full filter directives.
     update_tracing(&reload_handle, &config.tracing_filter_directives())?;
     set_title(config.set_title());
     display_time(config.display_time());
+    set_unattended(config.unattended());
     set_desktop_notifications(config.notify_each_step());
 
     debug!("Version: {}", crate_version!());
@@ -127,6 +146,50 @@ fn run() -> Result<()> {
     debug!("self-update Feature Enabled: {:?}", cfg!(feature = "self-update"));
     debug!("Configuration: {:?}", config);
 
+    match config.action() {
+        Some(Action::Detect { json }) => {
+            let sudo = config.sudo_command().map_or_else(sudo::Sudo::detect, sudo::Sudo::new);
+            let ctx = execution_context::ExecutionContext::new(executor::RunType::new(true), sudo, &config);
+            return registry::print_detection(&ctx, *json);
+        }
+        Some(Action::History {
+            command: Some(HistoryCommand::Show { id }),
+            ..
+        }) => return history::print_run(id),
+        Some(Action::History {
+            command: None,
+            last,
+            step,
+            failed,
+        }) => return history::print_runs(*last, *step, *failed),
+        Some(Action::Schedule {
+            command: ScheduleCommand::Install { weekly, cron, .. },
+        }) => {
+            let frequency = if *weekly {
+                schedule::Frequency::Weekly
+            } else {
+                schedule::Frequency::Daily
+            };
+            return schedule::install(&config, frequency, *cron);
+        }
+        Some(Action::Schedule {
+            command: ScheduleCommand::Uninstall,
+        }) => return schedule::uninstall(),
+        Some(Action::Rollback { .. }) => return history::print_snapshots(),
+        Some(Action::Config {
+            command: ConfigCommand::Check | ConfigCommand::Migrate { .. },
+        }) => unreachable!("The configuration is checked and migrated before loading it"),
+        Some(Action::Config {
+            command:
+                ConfigCommand::Show {
+                    format,
+                    origin,
+                    show_secrets,
+                },
+        }) => return config.show(*format, *origin, *show_secrets),
+        None => (),
+    }
+
     if config.run_in_tmux() && env::var("TOPGRADE_INSIDE_TMUX").is_err() {
         #[cfg(unix)]
         {
@@ -135,9 +198,6 @@ fn run() -> Result<()> {
         }
     }
 
-    let powershell = powershell::Powershell::new();
-    let should_run_powershell = powershell.profile().is_some() && config.should_run(Step::Powershell);
-    let emacs = emacs::Emacs::new();
     #[cfg(target_os = "linux")]
     let distribution = linux::Distribution::detect();
 
@@ -146,6 +206,42 @@ fn run() -> Result<()> {
     let ctx = execution_context::ExecutionContext::new(run_type, sudo, &config);
     let mut runner = runner::Runner::new(&ctx);
 
+    if config.resume() {
+        match history::interrupted_run()? {
+            Some((id, done)) => {
+                print_info(t!("Resuming the interrupted run {id}", id = id));
+                runner.resume(done);
+            }
+            None => print_warning(t!("The last run was not interrupted, running every step")),
+        }
+    }
+
+    if config.rerun_failed() {
+        match history::last_failures()? {
+            Some((id, failed)) if !failed.is_empty() => {
+                let keys: Vec<_> = failed.iter().map(|s| s.key.as_str()).collect();
+                print_info(t!(
+                    "Running the steps that failed in the run {id}: {steps}",
+                    id = id,
+                    steps = keys.join(", ")
+                ));
+                runner.only(failed);
+            }
+            Some(_) | None => {
+                println!("{}", t!("No step failed in the last run"));
+                return Ok(());
+            }
+        }
+    }
+
+    if config.log_file() {
+        let id = history::run_id(runner.report().started_at());
+        match log_file::start(&id, config.log_rotation()) {
+            Ok(path) => debug!("Logging to {}", path.display()),
+            Err(e) => print_warning(t!("Failed to open the log file: {error}", error = format!("{e:?}"))),
+        }
+    }
+
     // If
     //
     // 1. the breaking changes notification shouldnot be skipped
@@ -155,10 +251,15 @@ fn run() -> Result<()> {
     if !should_skip() && first_run_of_major_release()? {
         print_breaking_changes();
 
-        if prompt_yesno("Confirmed?")? {
+        // Nobody is there to confirm, so the changes are shown again on the next run.
+        if config.unattended() {
+            print_warning(t!(
+                "Running unattended, the breaking changes will be shown again on the next run"
+            ));
+        } else if prompt_yesno("Confirmed?")? {
             write_keep_file()?;
         } else {
-            exit(1);
+            return Err(io::Error::from(io::ErrorKind::Interrupted).into());
         }
     }
 
@@ -167,7 +268,8 @@ fn run() -> Result<()> {
     // 2. it is not disabled from configuration (env var/CLI opt/file)
     #[cfg(feature = "self-update")]
     {
-        let should_self_update = env::var("TOPGRADE_NO_SELF_UPGRADE").is_err() && !config.no_self_update();
+        let should_self_update =
+            env::var("TOPGRADE_NO_SELF_UPGRADE").is_err() && !config.no_self_update() && !config.check();
 
         if should_self_update {
             runner.execute(Step::SelfUpdate, "Self Update", || self_update::self_update(&ctx))?;
@@ -181,373 +283,146 @@ fn run() -> Result<()> {
         None
     };
 
-    if let Some(commands) = config.pre_commands() {
-        for (name, command) in commands {
-            generic::run_custom_command(name, command, &ctx)?;
+    if config.check() {
+        // Only the steps of the registry know how to check for updates, without side effects.
+        for entry in registry::entries() {
+            runner
+                .plan(entry.step, entry.name, || entry.check(&ctx))
+                .detected_by(|| {
+                    if entry.checks() {
+                        entry.detect(&ctx).map(drop)
+                    } else {
+                        Ok(())
+                    }
+                })
+                .before(entry.before);
         }
-    }
-
-    if config.pre_sudo() {
-        if let Some(sudo) = ctx.sudo() {
-            sudo.elevate(&ctx)?;
+    } else {
+        if let Some(commands) = config.pre_commands() {
+            for (name, command) in commands {
+                generic::run_custom_command(name, command, &ctx)?;
+            }
         }
-    }
 
-    if let Some(topgrades) = config.remote_topgrades() {
-        for remote_topgrade in topgrades.iter().filter(|t| config.should_execute_remote(hostname(), t)) {
-            runner.execute(Step::Remotes, format!("Remote ({remote_topgrade})"), || {
-                ssh::ssh_step(&ctx, remote_topgrade)
-            })?;
+        if config.pre_sudo() {
+            if let Some(sudo) = ctx.sudo() {
+                sudo.elevate(&ctx)?;
+            }
         }
-    }
-
-    #[cfg(windows)]
-    {
-        runner.execute(Step::Wsl, "WSL", || windows::run_wsl_topgrade(&ctx))?;
-        runner.execute(Step::WslUpdate, "WSL", || windows::update_wsl(&ctx))?;
-        runner.execute(Step::Chocolatey, "Chocolatey", || windows::run_chocolatey(&ctx))?;
-        runner.execute(Step::Scoop, "Scoop", || windows::run_scoop(&ctx))?;
-        runner.execute(Step::Winget, "Winget", || windows::run_winget(&ctx))?;
-        runner.execute(Step::System, "Windows update", || windows::windows_update(&ctx))?;
-        runner.execute(Step::MicrosoftStore, "Microsoft Store", || {
-            windows::microsoft_store(&ctx)
-        })?;
-    }
-
-    #[cfg(target_os = "linux")]
-    {
-        // NOTE: Due to breaking `nu` updates, `packer.nu` needs to be updated before `nu` get updated
-        // by other package managers.
-        runner.execute(Step::Shell, "packer.nu", || linux::run_packer_nu(&ctx))?;
 
-        match &distribution {
-            Ok(distribution) => {
-                runner.execute(Step::System, "System update", || distribution.upgrade(&ctx))?;
-            }
-            Err(e) => {
-                println!("{}", t!("Error detecting current distribution: {error}", error = e));
+        if let Some(topgrades) = config.remote_topgrades() {
+            for remote_topgrade in topgrades.iter().filter(|t| config.should_execute_remote(hostname(), t)) {
+                runner.plan(Step::Remotes, format!("Remote ({remote_topgrade})"), || {
+                    ssh::ssh_step(&ctx, remote_topgrade)
+                });
             }
         }
-        runner.execute(Step::ConfigUpdate, "config-update", || linux::run_config_update(&ctx))?;
-
-        runner.execute(Step::AM, "am", || linux::run_am(&ctx))?;
-        runner.execute(Step::AppMan, "appman", || linux::run_appman(&ctx))?;
-        runner.execute(Step::DebGet, "deb-get", || linux::run_deb_get(&ctx))?;
-        runner.execute(Step::Toolbx, "toolbx", || toolbx::run_toolbx(&ctx))?;
-        runner.execute(Step::Snap, "snap", || linux::run_snap(&ctx))?;
-        runner.execute(Step::Pacstall, "pacstall", || linux::run_pacstall(&ctx))?;
-        runner.execute(Step::Pacdef, "pacdef", || linux::run_pacdef(&ctx))?;
-        runner.execute(Step::Protonup, "protonup", || linux::run_protonup_update(&ctx))?;
-        runner.execute(Step::Distrobox, "distrobox", || linux::run_distrobox_update(&ctx))?;
-        runner.execute(Step::DkpPacman, "dkp-pacman", || linux::run_dkp_pacman_update(&ctx))?;
-        runner.execute(Step::System, "pihole", || linux::run_pihole_update(&ctx))?;
-        runner.execute(Step::Firmware, "Firmware upgrades", || linux::run_fwupdmgr(&ctx))?;
-        runner.execute(Step::Restarts, "Restarts", || linux::run_needrestart(&ctx))?;
-
-        runner.execute(Step::Flatpak, "Flatpak", || linux::run_flatpak(&ctx))?;
-        runner.execute(Step::BrewFormula, "Brew", || {
-            unix::run_brew_formula(&ctx, unix::BrewVariant::Path)
-        })?;
-        runner.execute(Step::Lure, "LURE", || linux::run_lure_update(&ctx))?;
-        runner.execute(Step::Waydroid, "Waydroid", || linux::run_waydroid(&ctx))?;
-        runner.execute(Step::AutoCpufreq, "auto-cpufreq", || linux::run_auto_cpufreq(&ctx))?;
-        runner.execute(Step::CinnamonSpices, "Cinnamon spices", || {
-            linux::run_cinnamon_spices_updater(&ctx)
-        })?;
-    }
 
-    #[cfg(target_os = "macos")]
-    {
-        runner.execute(Step::BrewFormula, "Brew (ARM)", || {
-            unix::run_brew_formula(&ctx, unix::BrewVariant::MacArm)
-        })?;
-        runner.execute(Step::BrewFormula, "Brew (Intel)", || {
-            unix::run_brew_formula(&ctx, unix::BrewVariant::MacIntel)
-        })?;
-        runner.execute(Step::BrewFormula, "Brew", || {
-            unix::run_brew_formula(&ctx, unix::BrewVariant::Path)
-        })?;
-        runner.execute(Step::BrewCask, "Brew Cask (ARM)", || {
-            unix::run_brew_cask(&ctx, unix::BrewVariant::MacArm)
-        })?;
-        runner.execute(Step::BrewCask, "Brew Cask (Intel)", || {
-            unix::run_brew_cask(&ctx, unix::BrewVariant::MacIntel)
-        })?;
-        runner.execute(Step::BrewCask, "Brew Cask", || {
-            unix::run_brew_cask(&ctx, unix::BrewVariant::Path)
-        })?;
-        runner.execute(Step::Macports, "MacPorts", || macos::run_macports(&ctx))?;
-        runner.execute(Step::Xcodes, "Xcodes", || macos::update_xcodes(&ctx))?;
-        runner.execute(Step::Sparkle, "Sparkle", || macos::run_sparkle(&ctx))?;
-        runner.execute(Step::Mas, "App Store", || macos::run_mas(&ctx))?;
-        runner.execute(Step::System, "System upgrade", || macos::upgrade_macos(&ctx))?;
-    }
+        for entry in registry::entries() {
+            runner
+                .plan(entry.step, entry.name, || entry.run(&ctx))
+                .detected_by(|| entry.detect(&ctx).map(drop))
+                .before(entry.before);
+        }
 
-    #[cfg(target_os = "dragonfly")]
-    {
-        runner.execute(Step::Pkg, "DragonFly BSD Packages", || {
-            dragonfly::upgrade_packages(&ctx)
-        })?;
-        runner.execute(Step::Audit, "DragonFly Audit", || dragonfly::audit_packages(&ctx))?;
-    }
+        if let Some(commands) = config.commands() {
+            for (name, command) in commands {
+                if config.should_run_custom_command(name) {
+                    runner.plan(Step::CustomCommands, name, || {
+                        generic::run_custom_command(name, command, &ctx)
+                    });
+                }
+            }
+        }
 
-    #[cfg(target_os = "freebsd")]
-    {
-        runner.execute(Step::Pkg, "FreeBSD Packages", || freebsd::upgrade_packages(&ctx))?;
-        runner.execute(Step::System, "FreeBSD Upgrade", || freebsd::upgrade_freebsd(&ctx))?;
-        runner.execute(Step::Audit, "FreeBSD Audit", || freebsd::audit_packages(&ctx))?;
+        if config.should_run(Step::Vagrant) {
+            if let Ok(boxes) = vagrant::collect_boxes(&ctx) {
+                for vagrant_box in boxes {
+                    let ctx = &ctx;
+                    runner.plan(
+                        Step::Vagrant,
+                        format!("Vagrant ({})", vagrant_box.smart_name()),
+                        move || vagrant::topgrade_vagrant_box(ctx, &vagrant_box),
+                    );
+                }
+            }
+        }
+        runner.plan(Step::Vagrant, "Vagrant boxes", || vagrant::upgrade_vagrant_boxes(&ctx));
     }
+    runner.run_plan()?;
 
-    #[cfg(target_os = "openbsd")]
+    #[cfg(target_os = "linux")]
     {
-        runner.execute(Step::Pkg, "OpenBSD Packages", || openbsd::upgrade_packages(&ctx))?;
-        runner.execute(Step::System, "OpenBSD Upgrade", || openbsd::upgrade_openbsd(&ctx))?;
+        let reboot_required = distribution.as_ref().ok().and_then(|d| d.reboot_required());
+        runner.report_mut().set_reboot_required(reboot_required);
     }
 
-    #[cfg(target_os = "android")]
-    {
-        runner.execute(Step::Pkg, "Termux Packages", || android::upgrade_packages(&ctx))?;
-    }
+    let show_skipped = config.verbose() || config.show_skipped();
+    let summary: Vec<_> = runner
+        .report()
+        .data()
+        .iter()
+        .filter(|r| show_skipped || !matches!(r.result, StepResult::Skipped(_)))
+        .collect();
+    if !summary.is_empty() {
+        print_separator(t!("Summary"));
 
-    #[cfg(unix)]
-    {
-        runner.execute(Step::Yadm, "yadm", || unix::run_yadm(&ctx))?;
-        runner.execute(Step::Nix, "nix", || unix::run_nix(&ctx))?;
-        runner.execute(Step::Nix, "nix upgrade-nix", || unix::run_nix_self_upgrade(&ctx))?;
-        runner.execute(Step::Guix, "guix", || unix::run_guix(&ctx))?;
-        runner.execute(Step::HomeManager, "home-manager", || unix::run_home_manager(&ctx))?;
-        runner.execute(Step::Asdf, "asdf", || unix::run_asdf(&ctx))?;
-        runner.execute(Step::Mise, "mise", || unix::run_mise(&ctx))?;
-        runner.execute(Step::Pkgin, "pkgin", || unix::run_pkgin(&ctx))?;
-        runner.execute(Step::BunPackages, "bun-packages", || unix::run_bun_packages(&ctx))?;
-        runner.execute(Step::Shell, "zr", || zsh::run_zr(&ctx))?;
-        runner.execute(Step::Shell, "antibody", || zsh::run_antibody(&ctx))?;
-        runner.execute(Step::Shell, "antidote", || zsh::run_antidote(&ctx))?;
-        runner.execute(Step::Shell, "antigen", || zsh::run_antigen(&ctx))?;
-        runner.execute(Step::Shell, "zgenom", || zsh::run_zgenom(&ctx))?;
-        runner.execute(Step::Shell, "zplug", || zsh::run_zplug(&ctx))?;
-        runner.execute(Step::Shell, "zinit", || zsh::run_zinit(&ctx))?;
-        runner.execute(Step::Shell, "zi", || zsh::run_zi(&ctx))?;
-        runner.execute(Step::Shell, "zim", || zsh::run_zim(&ctx))?;
-        runner.execute(Step::Shell, "oh-my-zsh", || zsh::run_oh_my_zsh(&ctx))?;
-        runner.execute(Step::Shell, "oh-my-bash", || unix::run_oh_my_bash(&ctx))?;
-        runner.execute(Step::Shell, "fisher", || unix::run_fisher(&ctx))?;
-        runner.execute(Step::Shell, "bash-it", || unix::run_bashit(&ctx))?;
-        runner.execute(Step::Shell, "oh-my-fish", || unix::run_oh_my_fish(&ctx))?;
-        runner.execute(Step::Shell, "fish-plug", || unix::run_fish_plug(&ctx))?;
-        runner.execute(Step::Shell, "fundle", || unix::run_fundle(&ctx))?;
-        runner.execute(Step::Tmux, "tmux", || tmux::run_tpm(&ctx))?;
-        runner.execute(Step::Tldr, "TLDR", || unix::run_tldr(&ctx))?;
-        runner.execute(Step::Pearl, "pearl", || unix::run_pearl(&ctx))?;
-        #[cfg(not(any(target_os = "macos", target_os = "android")))]
-        runner.execute(Step::GnomeShellExtensions, "Gnome Shell Extensions", || {
-            unix::upgrade_gnome_extensions(&ctx)
-        })?;
-        runner.execute(Step::Pyenv, "pyenv", || unix::run_pyenv(&ctx))?;
-        runner.execute(Step::Sdkman, "SDKMAN!", || unix::run_sdkman(&ctx))?;
-        runner.execute(Step::Rcm, "rcm", || unix::run_rcm(&ctx))?;
-        runner.execute(Step::Maza, "maza", || unix::run_maza(&ctx))?;
-    }
+        for r in &summary {
+            let duration = (!matches!(r.result, StepResult::Skipped(_))).then(|| r.duration());
+            print_result(&r.key, &r.result, duration);
+        }
 
-    #[cfg(not(any(
-        target_os = "freebsd",
-        target_os = "openbsd",
-        target_os = "netbsd",
-        target_os = "dragonfly"
-    )))]
-    {
-        runner.execute(Step::Atom, "apm", || generic::run_apm(&ctx))?;
-    }
+        if let Some(slowest) = config.slowest() {
+            let mut ran: Vec<_> = summary
+                .iter()
+                .filter(|r| !matches!(r.result, StepResult::Skipped(_)))
+                .collect();
+            ran.sort_by_key(|r| std::cmp::Reverse(r.duration()));
 
-    // The following update function should be executed on all OSes.
-    runner.execute(Step::Fossil, "fossil", || generic::run_fossil(&ctx))?;
-    runner.execute(Step::Elan, "elan", || generic::run_elan(&ctx))?;
-    runner.execute(Step::Rye, "rye", || generic::run_rye(&ctx))?;
-    runner.execute(Step::Rustup, "rustup", || generic::run_rustup(&ctx))?;
-    runner.execute(Step::Juliaup, "juliaup", || generic::run_juliaup(&ctx))?;
-    runner.execute(Step::Dotnet, ".NET", || generic::run_dotnet_upgrade(&ctx))?;
-    runner.execute(Step::Choosenim, "choosenim", || generic::run_choosenim(&ctx))?;
-    runner.execute(Step::Cargo, "cargo", || generic::run_cargo_update(&ctx))?;
-    runner.execute(Step::Flutter, "Flutter", || generic::run_flutter_upgrade(&ctx))?;
-    runner.execute(Step::Go, "go-global-update", || go::run_go_global_update(&ctx))?;
-    runner.execute(Step::Go, "gup", || go::run_go_gup(&ctx))?;
-    runner.execute(Step::Emacs, "Emacs", || emacs.upgrade(&ctx))?;
-    runner.execute(Step::Opam, "opam", || generic::run_opam_update(&ctx))?;
-    runner.execute(Step::Vcpkg, "vcpkg", || generic::run_vcpkg_update(&ctx))?;
-    runner.execute(Step::Pipx, "pipx", || generic::run_pipx_update(&ctx))?;
-    runner.execute(Step::Pipxu, "pipxu", || generic::run_pipxu_update(&ctx))?;
-    runner.execute(Step::Vscode, "Visual Studio Code extensions", || {
-        generic::run_vscode_extensions_update(&ctx)
-    })?;
-    runner.execute(Step::Vscodium, "VSCodium extensions", || {
-        generic::run_vscodium_extensions_update(&ctx)
-    })?;
-    runner.execute(Step::Conda, "conda", || generic::run_conda_update(&ctx))?;
-    runner.execute(Step::Mamba, "mamba", || generic::run_mamba_update(&ctx))?;
-    runner.execute(Step::Pixi, "pixi", || generic::run_pixi_update(&ctx))?;
-    runner.execute(Step::Miktex, "miktex", || generic::run_miktex_packages_update(&ctx))?;
-    runner.execute(Step::Pip3, "pip3", || generic::run_pip3_update(&ctx))?;
-    runner.execute(Step::PipReview, "pip-review", || generic::run_pip_review_update(&ctx))?;
-    runner.execute(Step::PipReviewLocal, "pip-review (local)", || {
-        generic::run_pip_review_local_update(&ctx)
-    })?;
-    runner.execute(Step::Pipupgrade, "pipupgrade", || generic::run_pipupgrade_update(&ctx))?;
-    runner.execute(Step::Ghcup, "ghcup", || generic::run_ghcup_update(&ctx))?;
-    runner.execute(Step::Stack, "stack", || generic::run_stack_update(&ctx))?;
-    runner.execute(Step::Tlmgr, "tlmgr", || generic::run_tlmgr_update(&ctx))?;
-    runner.execute(Step::Myrepos, "myrepos", || generic::run_myrepos_update(&ctx))?;
-    runner.execute(Step::Chezmoi, "chezmoi", || generic::run_chezmoi_update(&ctx))?;
-    runner.execute(Step::Jetpack, "jetpack", || generic::run_jetpack(&ctx))?;
-    runner.execute(Step::Vim, "vim", || vim::upgrade_vim(&ctx))?;
-    runner.execute(Step::Vim, "Neovim", || vim::upgrade_neovim(&ctx))?;
-    runner.execute(Step::Vim, "The Ultimate vimrc", || vim::upgrade_ultimate_vimrc(&ctx))?;
-    runner.execute(Step::Vim, "voom", || vim::run_voom(&ctx))?;
-    runner.execute(Step::Kakoune, "Kakoune", || kakoune::upgrade_kak_plug(&ctx))?;
-    runner.execute(Step::Helix, "helix", || generic::run_helix_grammars(&ctx))?;
-    runner.execute(Step::Node, "npm", || node::run_npm_upgrade(&ctx))?;
-    runner.execute(Step::Yarn, "yarn", || node::run_yarn_upgrade(&ctx))?;
-    runner.execute(Step::Pnpm, "pnpm", || node::run_pnpm_upgrade(&ctx))?;
-    runner.execute(Step::VoltaPackages, "volta packages", || {
-        node::run_volta_packages_upgrade(&ctx)
-    })?;
-    runner.execute(Step::Containers, "Containers", || containers::run_containers(&ctx))?;
-    runner.execute(Step::Deno, "deno", || node::deno_upgrade(&ctx))?;
-    runner.execute(Step::Composer, "composer", || generic::run_composer_update(&ctx))?;
-    runner.execute(Step::Krew, "krew", || generic::run_krew_upgrade(&ctx))?;
-    runner.execute(Step::Helm, "helm", || generic::run_helm_repo_update(&ctx))?;
-    runner.execute(Step::Gem, "gem", || generic::run_gem(&ctx))?;
-    runner.execute(Step::RubyGems, "rubygems", || generic::run_rubygems(&ctx))?;
-    runner.execute(Step::Julia, "julia", || generic::update_julia_packages(&ctx))?;
-    runner.execute(Step::Haxelib, "haxelib", || generic::run_haxelib_update(&ctx))?;
-    runner.execute(Step::Sheldon, "sheldon", || generic::run_sheldon(&ctx))?;
-    runner.execute(Step::Stew, "stew", || generic::run_stew(&ctx))?;
-    runner.execute(Step::Rtcl, "rtcl", || generic::run_rtcl(&ctx))?;
-    runner.execute(Step::Bin, "bin", || generic::bin_update(&ctx))?;
-    runner.execute(Step::Gcloud, "gcloud", || generic::run_gcloud_components_update(&ctx))?;
-    runner.execute(Step::Micro, "micro", || generic::run_micro(&ctx))?;
-    runner.execute(Step::Raco, "raco", || generic::run_raco_update(&ctx))?;
-    runner.execute(Step::Spicetify, "spicetify", || generic::spicetify_upgrade(&ctx))?;
-    runner.execute(Step::GithubCliExtensions, "GitHub CLI Extensions", || {
-        generic::run_ghcli_extensions_upgrade(&ctx)
-    })?;
-    runner.execute(Step::Bob, "Bob", || generic::run_bob(&ctx))?;
-    runner.execute(Step::Certbot, "Certbot", || generic::run_certbot(&ctx))?;
-    runner.execute(Step::GitRepos, "Git Repositories", || git::run_git_pull(&ctx))?;
-    runner.execute(Step::ClamAvDb, "ClamAV Databases", || generic::run_freshclam(&ctx))?;
-    runner.execute(Step::PlatformioCore, "PlatformIO Core", || {
-        generic::run_platform_io(&ctx)
-    })?;
-    runner.execute(Step::Lensfun, "Lensfun's database update", || {
-        generic::run_lensfun_update_data(&ctx)
-    })?;
-    runner.execute(Step::Poetry, "Poetry", || generic::run_poetry(&ctx))?;
-    runner.execute(Step::Uv, "uv", || generic::run_uv(&ctx))?;
-    runner.execute(Step::Zvm, "ZVM", || generic::run_zvm(&ctx))?;
-    runner.execute(Step::Aqua, "aqua", || generic::run_aqua(&ctx))?;
-    runner.execute(Step::Bun, "bun", || generic::run_bun(&ctx))?;
-    runner.execute(Step::Zigup, "zigup", || generic::run_zigup(&ctx))?;
-    runner.execute(Step::JetbrainsToolbox, "JetBrains Toolbox", || {
-        generic::run_jetbrains_toolbox(&ctx)
-    })?;
-    runner.execute(Step::AndroidStudio, "Android Studio plugins", || {
-        generic::run_android_studio(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsAqua, "JetBrains Aqua plugins", || {
-        generic::run_jetbrains_aqua(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsClion, "JetBrains CLion plugins", || {
-        generic::run_jetbrains_clion(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsDatagrip, "JetBrains DataGrip plugins", || {
-        generic::run_jetbrains_datagrip(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsDataspell, "JetBrains DataSpell plugins", || {
-        generic::run_jetbrains_dataspell(&ctx)
-    })?;
-    // JetBrains dotCover has no CLI
-    // JetBrains dotMemory has no CLI
-    // JetBrains dotPeek has no CLI
-    // JetBrains dotTrace has no CLI
-    // JetBrains Fleet has a different CLI without a `fleet update` command.
-    runner.execute(Step::JetbrainsGateway, "JetBrains Gateway plugins", || {
-        generic::run_jetbrains_gateway(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsGoland, "JetBrains GoLand plugins", || {
-        generic::run_jetbrains_goland(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsIdea, "JetBrains IntelliJ IDEA plugins", || {
-        generic::run_jetbrains_idea(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsMps, "JetBrains MPS plugins", || {
-        generic::run_jetbrains_mps(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsPhpstorm, "JetBrains PhpStorm plugins", || {
-        generic::run_jetbrains_phpstorm(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsPycharm, "JetBrains PyCharm plugins", || {
-        generic::run_jetbrains_pycharm(&ctx)
-    })?;
-    // JetBrains ReSharper has no CLI (it's a VSCode extension)
-    // JetBrains ReSharper C++ has no CLI (it's a VSCode extension)
-    runner.execute(Step::JetbrainsRider, "JetBrains Rider plugins", || {
-        generic::run_jetbrains_rider(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsRubymine, "JetBrains RubyMine plugins", || {
-        generic::run_jetbrains_rubymine(&ctx)
-    })?;
-    runner.execute(Step::JetbrainsRustrover, "JetBrains RustRover plugins", || {
-        generic::run_jetbrains_rustrover(&ctx)
-    })?;
-    // JetBrains Space Desktop does not have a CLI
-    runner.execute(Step::JetbrainsWebstorm, "JetBrains WebStorm plugins", || {
-        generic::run_jetbrains_webstorm(&ctx)
-    })?;
-    runner.execute(Step::Yazi, "Yazi packages", || generic::run_yazi(&ctx))?;
-
-    if should_run_powershell {
-        runner.execute(Step::Powershell, "Powershell Modules Update", || {
-            powershell.update_modules(&ctx)
-        })?;
-    }
+            print_separator(t!("Slowest steps"));
+            for r in ran.into_iter().take(slowest) {
+                println!("{}: {}", r.key, format_duration(r.duration()));
+            }
+        }
 
-    if let Some(commands) = config.commands() {
-        for (name, command) in commands {
-            if config.should_run_custom_command(name) {
-                runner.execute(Step::CustomCommands, name, || {
-                    generic::run_custom_command(name, command, &ctx)
-                })?;
+        #[cfg(target_os = "linux")]
+        {
+            if let Ok(distribution) = &distribution {
+                distribution.show_summary();
             }
         }
+
+        if let Some(reason) = runner.report().reboot_required() {
+            print_warning(t!("A reboot is required: {reason}", reason = reason));
+        }
     }
 
-    if config.should_run(Step::Vagrant) {
-        if let Ok(boxes) = vagrant::collect_boxes(&ctx) {
-            for vagrant_box in boxes {
-                runner.execute(Step::Vagrant, format!("Vagrant ({})", vagrant_box.smart_name()), || {
-                    vagrant::topgrade_vagrant_box(&ctx, &vagrant_box)
-                })?;
+    let updates_available = config.check() && runner.report().data().iter().any(|r| !r.packages.is_empty());
+    if config.check() {
+        print_separator(t!("Available updates"));
+        if updates_available {
+            for r in runner.report().data().iter().filter(|r| !r.packages.is_empty()) {
+                println!("{}: {}", r.key, r.packages.len());
             }
+        } else {
+            println!("{}", t!("No updates available"));
         }
     }
-    runner.execute(Step::Vagrant, "Vagrant boxes", || vagrant::upgrade_vagrant_boxes(&ctx))?;
 
-    if !runner.report().data().is_empty() {
-        print_separator(t!("Summary"));
-
-        for (key, result) in runner.report().data() {
-            print_result(key, result);
-        }
+    if let Some(format) = config.report_format() {
+        runner.report().write(format, config.report_file())?;
+    }
 
-        #[cfg(target_os = "linux")]
+    if !config.dry_run() && !config.check() {
+        if let Err(e) = history::save_run(runner.report(), config.history_rotation())
+            .and_then(|()| history::append_package_changes(runner.report()))
         {
-            if let Ok(distribution) = &distribution {
-                distribution.show_summary();
-            }
+            print_warning(t!("Failed to save the history: {error}", error = format!("{e:?}")));
         }
     }
 
     let mut post_command_failed = false;
-    if let Some(commands) = config.post_commands() {
+    if let Some(commands) = config.post_commands().as_ref().filter(|_| !config.check()) {
         for (name, command) in commands {
             if generic::run_custom_command(name, command, &ctx).is_err() {
                 post_command_failed = true;
@@ -555,7 +430,24 @@ fn run() -> Result<()> {
         }
     }
 
-    if config.keep_at_end() {
+    let count_ignored = config.count_ignored_failures();
+    let failed = post_command_failed
+        || runner
+            .report()
+            .data()
+            .iter()
+            .any(|r| r.result.failed() || (count_ignored && matches!(r.result, StepResult::Ignored)));
+
+    if let Err(e) = notify::notify_run(&config, runner.report(), failed) {
+        print_warning(t!(
+            "Failed to send the notifications: {error}",
+            error = format!("{e:?}")
+        ));
+    }
+
+    if config.keep_at_end() && config.unattended() {
+        print_warning(t!("Running unattended, not waiting for a key press"));
+    } else if config.keep_at_end() {
         print_info(t!("\n(R)eboot\n(S)hell\n(Q)uit"));
         loop {
             match get_key() {
@@ -574,8 +466,6 @@ fn run() -> Result<()> {
         }
     }
 
-    let failed = post_command_failed || runner.report().data().iter().any(|(_, result)| result.failed());
-
     if !config.skip_notify() {
         notify_desktop(
             if failed {
@@ -587,8 +477,25 @@ fn run() -> Result<()> {
         );
     }
 
+    let reboot_required = runner.report().reboot_required().is_some();
+    if reboot_required && config.auto_reboot() == AutoReboot::IfRequired && !(config.dry_run() || config.check()) {
+        if failed {
+            print_warning(t!("Not rebooting automatically because a step failed"));
+        } else {
+            reboot().context("Failed to reboot")?;
+        }
+    }
+
+    if config.load_failed() {
+        print_warning(t!("The configuration has errors, see `topgrade config check`"));
+    }
+
     if failed {
         Err(StepFailed.into())
+    } else if reboot_required {
+        Err(RebootRequired.into())
+    } else if updates_available {
+        Err(UpdatesAvailable.into())
     } else {
         Ok(())
     }
@@ -607,11 +514,18 @@ fn main() {
                 }
             }
 
-            let skip_print = (error.downcast_ref::<StepFailed>().is_some())
-                || (error
-                    .downcast_ref::<io::Error>()
-                    .filter(|io_error| io_error.kind() == io::ErrorKind::Interrupted)
-                    .is_some());
+            if error.downcast_ref::<RebootRequired>().is_some() {
+                exit(10);
+            }
+
+            if error.downcast_ref::<UpdatesAvailable>().is_some() {
+                exit(20);
+            }
+
+            let quit = error
+                .downcast_ref::<io::Error>()
+                .is_some_and(|io_error| io_error.kind() == io::ErrorKind::Interrupted);
+            let skip_print = error.downcast_ref::<StepFailed>().is_some() || quit;
 
             if !skip_print {
                 // The `Debug` implementation of `eyre::Result` prints a multi-line
@@ -619,6 +533,13 @@ fn main() {
                 // `.with_context(...)` calls.
                 println!("{}", t!("Error: {error}", error = format!("{:?}", error)));
             }
+
+            // The exit codes are documented in `topgrade --help`.
+            if error.downcast_ref::<ConfigError>().is_some() {
+                exit(2);
+            } else if quit {
+                exit(3);
+            }
             exit(1);
         }
     }
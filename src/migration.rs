@@ -0,0 +1,349 @@
+//! Migrations of the configuration files across breaking changes.
+//!
+//! A configuration file records the version of its format in its top-level `version` key, a file
+//! without one being at version 0. Every migration brings a file to the next version, and they are
+//! applied in order with a format-preserving TOML editor, so that the comments and the layout of
+//! the file are kept. `topgrade config migrate` writes the migrated files, while the files that
+//! are not migrated yet are migrated in memory when they are read.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+use console::style;
+use rust_i18n::t;
+use similar::{ChangeTag, TextDiff};
+use toml_edit::{DocumentMut, Item, TableLike, TomlError};
+use tracing::warn;
+
+use crate::config::split_includes;
+
+/// The migrations, in order. The migration at index `i` brings a file from version `i` to `i + 1`.
+const MIGRATIONS: [fn(&mut DocumentMut); 2] = [move_settings_into_misc, rename_jet_brains_toolbox];
+
+/// The version of the configuration format of this Topgrade.
+pub const CONFIG_VERSION: usize = MIGRATIONS.len();
+
+/// A configuration file migrated to the current version.
+#[derive(Debug)]
+pub struct Migration {
+    pub contents: String,
+    /// The version the file was at.
+    pub from: usize,
+    /// Whether the migrations changed anything else than the version.
+    pub changed: bool,
+}
+
+/// Migrate the contents of a configuration file to the current version. Returns `None` if it is
+/// up to date already. The parts of the file split at its `[include]` sections are migrated
+/// separately, as they are read, and the version is that of the first part.
+pub fn migrate(contents: &str) -> Result<Option<Migration>, TomlError> {
+    let mut documents = split_includes(contents)
+        .into_iter()
+        .map(str::parse::<DocumentMut>)
+        .collect::<Result<Vec<_>, _>>()?;
+    let from = documents[0]
+        .get("version")
+        .and_then(Item::as_integer)
+        .and_then(|version| usize::try_from(version).ok())
+        .unwrap_or(0);
+    if from >= CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    for document in &mut documents {
+        for migration in &MIGRATIONS[from..] {
+            migration(document);
+        }
+    }
+    let changed = documents.iter().map(DocumentMut::to_string).collect::<String>() != contents;
+    documents[0].insert("version", toml_edit::value(CONFIG_VERSION as i64));
+
+    Ok(Some(Migration {
+        contents: documents.iter().map(DocumentMut::to_string).collect(),
+        from,
+        changed,
+    }))
+}
+
+/// The contents of the configuration file at `path`, migrated in memory if they need to be.
+pub fn migrated<'c>(path: &Path, contents: &'c str) -> Cow<'c, str> {
+    match migrate(contents) {
+        Ok(Some(migration)) if migration.changed => {
+            warn!(
+                "{}",
+                t!(
+                    "{path} uses an older configuration format, run `topgrade config migrate` to update it",
+                    path = path.display()
+                )
+            );
+            Cow::Owned(migration.contents)
+        }
+        _ => Cow::Borrowed(contents),
+    }
+}
+
+/// Migrate the configuration files at `paths`, printing the changes. A backup of every file is
+/// written next to it before it is changed. With `dry_run`, only the changes are printed.
+pub fn migrate_files(paths: &[PathBuf], dry_run: bool) -> Result<()> {
+    for path in paths {
+        let contents = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let migration = migrate(&contents).with_context(|| format!("Failed to parse {}", path.display()))?;
+        let Some(migration) = migration else {
+            println!("{}", t!("{path} is up to date", path = path.display()));
+            continue;
+        };
+
+        print_diff(path, &contents, &migration.contents);
+        if dry_run {
+            println!(
+                "{}",
+                t!(
+                    "{path} would be migrated from version {from} to {to}",
+                    path = path.display(),
+                    from = migration.from,
+                    to = CONFIG_VERSION
+                )
+            );
+            continue;
+        }
+
+        let mut backup = path.clone().into_os_string();
+        backup.push(format!(".v{}.bak", migration.from));
+        let backup = PathBuf::from(backup);
+        fs::write(&backup, &contents).with_context(|| format!("Failed to write the backup {}", backup.display()))?;
+        fs::write(path, &migration.contents).with_context(|| format!("Failed to write {}", path.display()))?;
+        println!(
+            "{}",
+            t!(
+                "Migrated {path} from version {from} to {to}, the previous version is saved to {backup}",
+                path = path.display(),
+                from = migration.from,
+                to = CONFIG_VERSION,
+                backup = backup.display()
+            )
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the changes from `old` to `new` as a unified diff.
+fn print_diff(path: &Path, old: &str, new: &str) {
+    println!("{}", style(path.display()).bold());
+    for hunk in TextDiff::from_lines(old, new).unified_diff().iter_hunks() {
+        println!("{}", style(hunk.header()).cyan());
+        for change in hunk.iter_changes() {
+            let line = change.to_string_lossy();
+            let line = line.trim_end_matches('\n');
+            match change.tag() {
+                ChangeTag::Delete => println!("{}", style(format!("-{line}")).red()),
+                ChangeTag::Insert => println!("{}", style(format!("+{line}")).green()),
+                ChangeTag::Equal => println!(" {line}"),
+            }
+        }
+    }
+    println!();
+}
+
+/// The settings of `[misc]` used to be written at the top of the file, outside of any table.
+fn move_settings_into_misc(document: &mut DocumentMut) {
+    if document.get("misc").is_some_and(|misc| !misc.is_table()) {
+        return;
+    }
+
+    let settings: Vec<_> = document
+        .iter()
+        .filter(|(key, item)| *key != "version" && item.is_value())
+        .map(|(key, _)| key.to_string())
+        .collect();
+    if settings.is_empty() {
+        return;
+    }
+
+    let settings: Vec<_> = settings.iter().filter_map(|key| document.remove_entry(key)).collect();
+    let misc = document
+        .entry("misc")
+        .or_insert_with(toml_edit::table)
+        .as_table_mut()
+        .expect("checked above");
+    for (key, item) in settings {
+        if !misc.contains_key(key.get()) {
+            misc.insert_formatted(&key, item);
+        }
+    }
+}
+
+/// The `jet_brains_toolbox` step was renamed to `jetbrains_toolbox`.
+fn rename_jet_brains_toolbox(document: &mut DocumentMut) {
+    rename_step(document, "jet_brains_toolbox", "jetbrains_toolbox");
+}
+
+/// Rename the step `from` to `to` in the lists of steps and in the tables keyed by step, in the
+/// file and in its profiles.
+fn rename_step(document: &mut DocumentMut, from: &str, to: &str) {
+    rename_step_in(document.as_table_mut(), from, to);
+    if let Some(profiles) = document.get_mut("profile").and_then(Item::as_table_like_mut) {
+        for (_, profile) in profiles.iter_mut() {
+            if let Some(profile) = profile.as_table_like_mut() {
+                rename_step_in(profile, from, to);
+            }
+        }
+    }
+}
+
+fn rename_step_in(table: &mut dyn TableLike, from: &str, to: &str) {
+    if let Some(misc) = table.get_mut("misc").and_then(Item::as_table_like_mut) {
+        rename_in_lists(misc, &["disable", "only", "ignore_failures"], from, to);
+    }
+    if let Some(steps) = table.get_mut("step").and_then(Item::as_table_like_mut) {
+        for (_, step) in steps.iter_mut() {
+            if let Some(step) = step.as_table_like_mut() {
+                rename_in_lists(step, &["after", "before"], from, to);
+            }
+        }
+    }
+
+    for key in ["step", "hooks"] {
+        let Some(table) = table.get_mut(key).and_then(Item::as_table_like_mut) else {
+            continue;
+        };
+        if let Some(item) = table.remove(from) {
+            table.insert(to, item);
+        }
+    }
+}
+
+/// Rename `from` to `to` in the arrays at `keys` of `table`, keeping their formatting.
+fn rename_in_lists(table: &mut dyn TableLike, keys: &[&str], from: &str, to: &str) {
+    for key in keys {
+        let Some(steps) = table.get_mut(key).and_then(Item::as_array_mut) else {
+            continue;
+        };
+        for step in steps.iter_mut().filter(|step| step.as_str() == Some(from)) {
+            let decor = step.decor().clone();
+            *step = to.into();
+            *step.decor_mut() = decor;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::EXAMPLE_CONFIG;
+
+    #[test]
+    fn test_example_config_is_up_to_date() {
+        assert!(migrate(EXAMPLE_CONFIG).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let contents = "\
+# Settings
+assume_yes = true # Say yes
+
+[misc]
+# Steps to skip
+disable = [\"system\", \"jet_brains_toolbox\"]
+
+[step.jet_brains_toolbox]
+timeout = \"1h\"
+";
+        let migration = migrate(contents).unwrap().unwrap();
+        assert_eq!(migration.from, 0);
+        assert!(migration.changed);
+        assert_eq!(
+            migration.contents,
+            "\
+version = 2
+
+[misc]
+# Steps to skip
+disable = [\"system\", \"jetbrains_toolbox\"]
+# Settings
+assume_yes = true # Say yes
+
+[step.jetbrains_toolbox]
+timeout = \"1h\"
+"
+        );
+
+        let migration = migrate("[misc]\nassume_yes = true\n").unwrap().unwrap();
+        assert!(!migration.changed);
+        assert_eq!(migration.contents, "version = 2\n[misc]\nassume_yes = true\n");
+        assert!(migrate(&migration.contents).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rename_step() {
+        let contents = "\
+[misc]
+ignore_failures = [\"jet_brains_toolbox\"]
+
+[step.custom_commands]
+after = [\"jet_brains_toolbox\"]
+before = [\"system\", \"jet_brains_toolbox\"]
+
+[profile.work.misc]
+only = [\"jet_brains_toolbox\"]
+
+[profile.work.step.jet_brains_toolbox]
+before = [\"jet_brains_toolbox\"]
+
+[profile.work.hooks.jet_brains_toolbox]
+pre = \"true\"
+";
+        let mut document: DocumentMut = contents.parse().unwrap();
+        rename_jet_brains_toolbox(&mut document);
+        assert_eq!(
+            document.to_string(),
+            contents.replace("jet_brains_toolbox", "jetbrains_toolbox")
+        );
+    }
+
+    #[test]
+    fn test_migrate_includes() {
+        let contents = "\
+assume_yes = true
+
+[include]
+paths = [\"a.toml\"]
+
+[misc]
+disable = [\"jet_brains_toolbox\"]
+
+[include]
+paths = [\"b.toml\"]
+
+[step.jet_brains_toolbox]
+timeout = \"1h\"
+";
+        let migration = migrate(contents).unwrap().unwrap();
+        assert_eq!(
+            migration.contents,
+            "\
+version = 2
+
+[misc]
+assume_yes = true
+
+[include]
+paths = [\"a.toml\"]
+
+[misc]
+disable = [\"jetbrains_toolbox\"]
+
+[include]
+paths = [\"b.toml\"]
+
+[step.jetbrains_toolbox]
+timeout = \"1h\"
+"
+        );
+
+        assert!(migrate("[misc]\n[misc]\n").is_err());
+    }
+}
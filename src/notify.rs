@@ -0,0 +1,402 @@
+//! Notifications about the results of runs, sent to the services configured in the `[notify]`
+//! section. Unlike desktop notifications, these also reach the user of a headless machine.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use chrono::Local;
+use color_eyre::eyre::{Context, Result};
+use indexmap::IndexMap;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use rust_i18n::t;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::command::CommandExt;
+use crate::config::Config;
+use crate::error::TopgradeError;
+use crate::report::{Report, StepReport, StepResult};
+use crate::terminal::{print_warning, shell};
+use crate::utils::hostname;
+
+/// How long to wait for a service to answer.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What is sent to the notifiers, serialized as the JSON payload of webhooks and command hooks.
+#[derive(Serialize, Debug)]
+pub struct Notification {
+    pub title: String,
+    pub message: String,
+    pub failed: bool,
+    pub hostname: Option<String>,
+    /// The report of the run, when the run is over.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<serde_json::Value>,
+}
+
+/// A service that notifications can be sent to.
+pub trait Notifier {
+    /// The name of the service, for error messages.
+    fn name(&self) -> &'static str;
+
+    fn send(&self, notification: &Notification) -> Result<()>;
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(TIMEOUT).build()
+}
+
+/// POSTs the notification as JSON to any URL, from `[notify.webhook]`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Webhook {
+    url: String,
+    #[serde(default)]
+    headers: IndexMap<String, String>,
+}
+
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        let mut request = agent().post(&self.url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        request.send_json(notification)?;
+        Ok(())
+    }
+}
+
+/// Publishes the message to an ntfy topic, from `[notify.ntfy]`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Ntfy {
+    /// The URL of the topic, e.g. `https://ntfy.sh/<topic>`.
+    url: String,
+    token: Option<String>,
+    priority: Option<String>,
+}
+
+impl Notifier for Ntfy {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        // Header values have to be ASCII, so the title goes to the query.
+        let mut request = agent().post(&self.url).query("title", &notification.title).set(
+            "Tags",
+            if notification.failed {
+                "warning"
+            } else {
+                "white_check_mark"
+            },
+        );
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {token}"));
+        }
+        if let Some(priority) = &self.priority {
+            request = request.set("Priority", priority);
+        }
+        request.send_string(&notification.message)?;
+        Ok(())
+    }
+}
+
+/// Sends the message to a Gotify server, from `[notify.gotify]`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Gotify {
+    url: String,
+    /// The token of the Gotify application.
+    token: String,
+    priority: Option<u8>,
+}
+
+impl Notifier for Gotify {
+    fn name(&self) -> &'static str {
+        "gotify"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        agent()
+            .post(&format!("{}/message", self.url.trim_end_matches('/')))
+            .set("X-Gotify-Key", &self.token)
+            .send_json(serde_json::json!({
+                "title": notification.title,
+                "message": notification.message,
+                "priority": self.priority.unwrap_or(5),
+            }))?;
+        Ok(())
+    }
+}
+
+/// How to secure the connection to the SMTP server.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// TLS from the start, usually on port 465.
+    Tls,
+    /// Plain connection upgraded to TLS, usually on port 587.
+    #[default]
+    Starttls,
+    /// No encryption at all, e.g. for a relay on the local machine.
+    None,
+}
+
+/// Sends an email through an SMTP server, from `[notify.email]`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Email {
+    server: String,
+    port: Option<u16>,
+    #[serde(default)]
+    security: SmtpSecurity,
+    username: Option<String>,
+    password: Option<String>,
+    from: String,
+    to: Vec<String>,
+}
+
+impl Notifier for Email {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        let mut message = Message::builder()
+            .from(self.from.parse::<Mailbox>()?)
+            .subject(&notification.title)
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.parse::<Mailbox>()?);
+        }
+        let message = message.body(notification.message.clone())?;
+
+        let mut transport = match self.security {
+            SmtpSecurity::Tls => SmtpTransport::relay(&self.server)?,
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&self.server)?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&self.server),
+        }
+        .timeout(Some(TIMEOUT));
+        if let Some(port) = self.port {
+            transport = transport.port(port);
+        }
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        transport.build().send(&message)?;
+        Ok(())
+    }
+}
+
+/// Runs a command with the notification as JSON on its standard input, from `[notify] command`.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(transparent)]
+pub struct CommandHook(String);
+
+impl Notifier for CommandHook {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn send(&self, notification: &Notification) -> Result<()> {
+        let mut child = Command::new(shell())
+            .arg("-c")
+            .arg(&self.0)
+            .env("TOPGRADE_TITLE", &notification.title)
+            .env("TOPGRADE_MESSAGE", &notification.message)
+            .env("TOPGRADE_FAILED", if notification.failed { "1" } else { "0" })
+            .stdin(Stdio::piped())
+            .spawn_checked()?;
+        let payload = serde_json::to_vec(notification)?;
+        // The command does not have to read its input.
+        child.stdin.take().unwrap().write_all(&payload).ok();
+
+        let status = child.wait().context("Failed to wait for the notification command")?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(TopgradeError::ProcessFailed(self.0.clone(), status).into())
+        }
+    }
+}
+
+fn label(result: &StepResult) -> String {
+    match result {
+        StepResult::Success => t!("OK"),
+        StepResult::Failure => t!("FAILED"),
+        StepResult::TimedOut => t!("TIMED OUT"),
+        StepResult::Ignored => t!("IGNORED"),
+        StepResult::Skipped(_) => t!("SKIPPED"),
+    }
+    .to_string()
+}
+
+fn send(config: &Config, notification: &Notification) {
+    for notifier in config.notifiers() {
+        debug!("Sending a notification with {}", notifier.name());
+        if let Err(e) = notifier.send(notification) {
+            print_warning(t!(
+                "Failed to send a notification with {notifier}: {error}",
+                notifier = notifier.name(),
+                error = format!("{e:?}")
+            ));
+        }
+    }
+}
+
+fn should_notify(config: &Config) -> bool {
+    !(config.dry_run() || config.skip_notify() || config.check())
+}
+
+/// Send the results of the run to the configured notifiers.
+pub fn notify_run(config: &Config, report: &Report, failed: bool) -> Result<()> {
+    if !should_notify(config) || (config.notify_only_on_failure() && !failed) {
+        return Ok(());
+    }
+
+    let message = report
+        .data()
+        .iter()
+        .filter(|r| !matches!(r.result, StepResult::Skipped(_)))
+        .map(|r| format!("{}: {}", r.key, label(&r.result)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let notification = Notification {
+        title: if failed {
+            t!("Topgrade finished with errors")
+        } else {
+            t!("Topgrade finished successfully")
+        }
+        .to_string(),
+        message,
+        failed,
+        hostname: hostname().ok(),
+        report: Some(serde_json::from_str(&report.to_json(Local::now())?)?),
+    };
+
+    send(config, &notification);
+    Ok(())
+}
+
+/// Send the failure of a step to the configured notifiers, if they are to be told about every
+/// failed step.
+pub fn notify_failed_step(config: &Config, report: &StepReport) {
+    if !should_notify(config) || !config.notify_each_failed_step() {
+        return;
+    }
+
+    let notification = Notification {
+        title: t!("{step_name} failed", step_name = report.key).to_string(),
+        message: report.errors.join("\n"),
+        failed: true,
+        hostname: hostname().ok(),
+        report: None,
+    };
+    send(config, &notification);
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use clap::Parser;
+
+    use super::*;
+    use crate::config::CommandLineArgs;
+
+    /// Answer a single HTTP request on a local port, returning the URL to send it to and the
+    /// request once it arrived.
+    fn stand_in() -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            reader
+                .into_inner()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            request
+        });
+        (url, handle)
+    }
+
+    fn notification() -> Notification {
+        Notification {
+            title: "Topgrade finished with errors".to_string(),
+            message: "cargo: OK\npipx: FAILED".to_string(),
+            failed: true,
+            hostname: Some("host".to_string()),
+            report: None,
+        }
+    }
+
+    #[test]
+    fn webhook_posts_json() {
+        let (url, request) = stand_in();
+        let webhook: Webhook = toml::from_str(&format!(
+            "url = \"{url}/hook\"\nheaders = {{ Authorization = \"Bearer secret\" }}"
+        ))
+        .unwrap();
+        webhook.send(&notification()).unwrap();
+
+        let request = request.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.contains("Authorization: Bearer secret\r\n"));
+        let body: serde_json::Value = serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["title"], "Topgrade finished with errors");
+        assert_eq!(body["failed"], true);
+        assert_eq!(body["hostname"], "host");
+    }
+
+    #[test]
+    fn ntfy_posts_the_message() {
+        let (url, request) = stand_in();
+        let ntfy: Ntfy = toml::from_str(&format!("url = \"{url}/topgrade\"\npriority = \"high\"")).unwrap();
+        ntfy.send(&notification()).unwrap();
+
+        let request = request.join().unwrap();
+        assert!(request.starts_with("POST /topgrade?title=Topgrade+finished+with+errors HTTP/1.1\r\n"));
+        assert!(request.contains("Tags: warning\r\n"));
+        assert!(request.contains("Priority: high\r\n"));
+        assert!(request.ends_with("\r\n\r\ncargo: OK\npipx: FAILED"));
+    }
+
+    #[test]
+    fn no_notification_when_checking() {
+        assert!(should_notify(&Config::from_args(CommandLineArgs::parse_from([
+            "topgrade"
+        ]))));
+        assert!(!should_notify(&Config::from_args(CommandLineArgs::parse_from([
+            "topgrade", "--check"
+        ]))));
+    }
+}
@@ -0,0 +1,665 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::Result;
+use console::{style, Style};
+use rust_i18n::t;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::config::Step;
+use crate::error::SkipStep;
+use crate::execution_context::ExecutionContext;
+use crate::report::{diff_versions, record_packages, PackageChange, Versions};
+#[allow(clippy::wildcard_imports)]
+use crate::steps::*;
+use crate::terminal::{print_line, print_separator};
+use crate::utils::{require, require_one, require_option};
+
+type RunFn = fn(&ExecutionContext) -> Result<()>;
+type DetectFn = fn(&ExecutionContext) -> Result<Option<PathBuf>>;
+type CheckFn = fn(&ExecutionContext) -> Result<Vec<PackageChange>>;
+type ListFn = fn(&ExecutionContext) -> Result<Versions>;
+
+/// How to find out whether a step applies to this machine without running it.
+enum Detect {
+    /// The step finds out by itself when it runs.
+    Nothing,
+    /// The step needs one of these binaries in `PATH`.
+    Binaries(&'static [&'static str]),
+    /// The step applies if this function succeeds, it returns the binary the step uses if any.
+    Custom(DetectFn),
+}
+
+/// A step of the registry.
+pub struct Entry {
+    pub step: Step,
+    /// The name of the step, used as its key in the output and the summary.
+    pub name: &'static str,
+    /// The entries of these steps have to run after this one.
+    pub before: &'static [Step],
+    detect: Detect,
+    run: RunFn,
+    /// Lists the packages the step would upgrade, for `--check`.
+    check: Option<CheckFn>,
+    /// Lists the installed packages, to find out what the step changed.
+    list: Option<ListFn>,
+}
+
+impl Entry {
+    const fn new(step: Step, name: &'static str, run: RunFn) -> Self {
+        Self {
+            step,
+            name,
+            before: &[],
+            detect: Detect::Nothing,
+            run,
+            check: None,
+            list: None,
+        }
+    }
+
+    const fn needs(self, binaries: &'static [&'static str]) -> Self {
+        Self {
+            detect: Detect::Binaries(binaries),
+            ..self
+        }
+    }
+
+    const fn detected_by(self, detect: DetectFn) -> Self {
+        Self {
+            detect: Detect::Custom(detect),
+            ..self
+        }
+    }
+
+    const fn checked_by(self, check: CheckFn) -> Self {
+        Self {
+            check: Some(check),
+            ..self
+        }
+    }
+
+    const fn tracked_by(self, list: ListFn) -> Self {
+        Self {
+            list: Some(list),
+            ..self
+        }
+    }
+
+    const fn runs_before(self, before: &'static [Step]) -> Self {
+        Self { before, ..self }
+    }
+
+    /// Tell whether the step can list the packages it would upgrade.
+    pub fn checks(&self) -> bool {
+        self.check.is_some()
+    }
+
+    /// Tell whether the step can find out if it applies without running.
+    pub fn detects(&self) -> bool {
+        !matches!(self.detect, Detect::Nothing)
+    }
+
+    /// Find out whether the step applies to this machine, returning the binary it uses if any.
+    /// Fails with `SkipStep` if it does not apply, or with another error if it cannot tell.
+    pub fn detect(&self, ctx: &ExecutionContext) -> Result<Option<PathBuf>> {
+        match self.detect {
+            Detect::Nothing => Ok(None),
+            Detect::Binaries([binary]) => require(binary).map(Some),
+            Detect::Binaries(binaries) => require_one(binaries).map(Some),
+            Detect::Custom(detect) => detect(ctx),
+        }
+    }
+
+    /// Run the step, or skip it if it does not apply to this machine. Records the packages it
+    /// changed if it can list them.
+    pub fn run(&self, ctx: &ExecutionContext) -> Result<()> {
+        self.detect(ctx)?;
+        let Some(list) = self.list.filter(|_| !ctx.run_type().dry()) else {
+            return (self.run)(ctx);
+        };
+
+        let before = list(ctx);
+        // Packages may have changed even if the step failed halfway
+        let result = (self.run)(ctx);
+        match (before, list(ctx)) {
+            (Ok(before), Ok(after)) => record_packages(diff_versions(&before, &after)),
+            (Err(e), _) | (_, Err(e)) => debug!("Failed to list the packages of {}: {e:?}", self.name),
+        }
+
+        result
+    }
+
+    /// List the packages the step would upgrade, without upgrading anything. Skips the step if it
+    /// does not apply to this machine or cannot check for updates.
+    pub fn check(&self, ctx: &ExecutionContext) -> Result<()> {
+        let Some(check) = self.check else {
+            return Err(SkipStep(t!("Checking for updates is not supported").to_string()).into());
+        };
+        self.detect(ctx)?;
+
+        print_separator(self.name);
+        let packages = check(ctx)?;
+        for package in &packages {
+            print_line(package.to_string());
+        }
+        print_line(t!("Updates available: {count}", count = packages.len()));
+        record_packages(packages);
+
+        Ok(())
+    }
+}
+
+/// All the steps that apply to the current OS, in the order in which they run by default.
+///
+/// Steps that expand into one entry per configured item, such as remote hosts, custom commands
+/// and Vagrant boxes, are not part of the registry.
+pub fn entries() -> impl Iterator<Item = &'static Entry> {
+    ENTRIES.iter().copied().flatten()
+}
+
+static ENTRIES: &[&[Entry]] = &[
+    #[cfg(windows)]
+    WINDOWS,
+    #[cfg(target_os = "linux")]
+    LINUX,
+    #[cfg(target_os = "macos")]
+    MACOS,
+    #[cfg(target_os = "dragonfly")]
+    DRAGONFLY,
+    #[cfg(target_os = "freebsd")]
+    FREEBSD,
+    #[cfg(target_os = "openbsd")]
+    OPENBSD,
+    #[cfg(target_os = "android")]
+    ANDROID,
+    #[cfg(unix)]
+    UNIX,
+    COMMON,
+];
+
+#[cfg(windows)]
+static WINDOWS: &[Entry] = &[
+    Entry::new(Step::Wsl, "WSL", windows::run_wsl_topgrade).needs(&["wsl"]),
+    Entry::new(Step::WslUpdate, "WSL", windows::update_wsl).needs(&["wsl"]),
+    Entry::new(Step::Chocolatey, "Chocolatey", windows::run_chocolatey).needs(&["choco"]),
+    Entry::new(Step::Scoop, "Scoop", windows::run_scoop).needs(&["scoop"]),
+    Entry::new(Step::Winget, "Winget", windows::run_winget).needs(&["winget"]),
+    Entry::new(Step::System, "Windows update", windows::windows_update),
+    Entry::new(Step::MicrosoftStore, "Microsoft Store", windows::microsoft_store),
+];
+
+#[cfg(target_os = "linux")]
+static LINUX: &[Entry] = &[
+    // Due to breaking `nu` updates, `packer.nu` needs to be updated before `nu` gets updated
+    // by other package managers.
+    Entry::new(Step::Shell, "packer.nu", linux::run_packer_nu)
+        .needs(&["nu"])
+        .runs_before(&[Step::System, Step::BrewFormula, Step::Cargo, Step::Nix]),
+    Entry::new(Step::System, "System update", upgrade_distribution)
+        .detected_by(detect_distribution)
+        .checked_by(check_distribution)
+        .tracked_by(list_distribution_packages),
+    Entry::new(Step::ConfigUpdate, "config-update", linux::run_config_update),
+    Entry::new(Step::AM, "am", linux::run_am).needs(&["am"]),
+    Entry::new(Step::AppMan, "appman", linux::run_appman).needs(&["appman"]),
+    Entry::new(Step::DebGet, "deb-get", linux::run_deb_get).needs(&["deb-get"]),
+    Entry::new(Step::Toolbx, "toolbx", toolbx::run_toolbx).needs(&["toolbox"]),
+    Entry::new(Step::Snap, "snap", linux::run_snap).needs(&["snap"]),
+    Entry::new(Step::Pacstall, "pacstall", linux::run_pacstall).needs(&["pacstall"]),
+    Entry::new(Step::Pacdef, "pacdef", linux::run_pacdef).needs(&["pacdef"]),
+    Entry::new(Step::Protonup, "protonup", linux::run_protonup_update).needs(&["protonup"]),
+    Entry::new(Step::Distrobox, "distrobox", linux::run_distrobox_update).needs(&["distrobox"]),
+    Entry::new(Step::DkpPacman, "dkp-pacman", linux::run_dkp_pacman_update).needs(&["dkp-pacman"]),
+    Entry::new(Step::System, "pihole", linux::run_pihole_update).needs(&["pihole"]),
+    Entry::new(Step::Firmware, "Firmware upgrades", linux::run_fwupdmgr).needs(&["fwupdmgr"]),
+    Entry::new(Step::Restarts, "Restarts", linux::run_needrestart).needs(&["needrestart"]),
+    Entry::new(Step::Flatpak, "Flatpak", linux::run_flatpak)
+        .needs(&["flatpak"])
+        .checked_by(linux::check_flatpak)
+        .tracked_by(linux::list_flatpak),
+    Entry::new(Step::BrewFormula, "Brew", |ctx| {
+        unix::run_brew_formula(ctx, unix::BrewVariant::Path)
+    })
+    .needs(&["brew"]),
+    Entry::new(Step::Lure, "LURE", linux::run_lure_update).needs(&["lure"]),
+    Entry::new(Step::Waydroid, "Waydroid", linux::run_waydroid).needs(&["waydroid"]),
+    Entry::new(Step::AutoCpufreq, "auto-cpufreq", linux::run_auto_cpufreq).needs(&["auto-cpufreq"]),
+    Entry::new(
+        Step::CinnamonSpices,
+        "Cinnamon spices",
+        linux::run_cinnamon_spices_updater,
+    )
+    .needs(&["cinnamon-spice-updater"]),
+];
+
+#[cfg(target_os = "macos")]
+static MACOS: &[Entry] = &[
+    Entry::new(Step::BrewFormula, "Brew (ARM)", |ctx| {
+        unix::run_brew_formula(ctx, unix::BrewVariant::MacArm)
+    }),
+    Entry::new(Step::BrewFormula, "Brew (Intel)", |ctx| {
+        unix::run_brew_formula(ctx, unix::BrewVariant::MacIntel)
+    }),
+    Entry::new(Step::BrewFormula, "Brew", |ctx| {
+        unix::run_brew_formula(ctx, unix::BrewVariant::Path)
+    })
+    .needs(&["brew"]),
+    Entry::new(Step::BrewCask, "Brew Cask (ARM)", |ctx| {
+        unix::run_brew_cask(ctx, unix::BrewVariant::MacArm)
+    }),
+    Entry::new(Step::BrewCask, "Brew Cask (Intel)", |ctx| {
+        unix::run_brew_cask(ctx, unix::BrewVariant::MacIntel)
+    }),
+    Entry::new(Step::BrewCask, "Brew Cask", |ctx| {
+        unix::run_brew_cask(ctx, unix::BrewVariant::Path)
+    })
+    .needs(&["brew"]),
+    Entry::new(Step::Macports, "MacPorts", macos::run_macports).needs(&["port"]),
+    Entry::new(Step::Xcodes, "Xcodes", macos::update_xcodes).needs(&["xcodes"]),
+    Entry::new(Step::Sparkle, "Sparkle", macos::run_sparkle).needs(&["sparkle"]),
+    Entry::new(Step::Mas, "App Store", macos::run_mas).needs(&["mas"]),
+    Entry::new(Step::System, "System upgrade", macos::upgrade_macos),
+];
+
+#[cfg(target_os = "dragonfly")]
+static DRAGONFLY: &[Entry] = &[
+    Entry::new(Step::Pkg, "DragonFly BSD Packages", dragonfly::upgrade_packages),
+    Entry::new(Step::Audit, "DragonFly Audit", dragonfly::audit_packages),
+];
+
+#[cfg(target_os = "freebsd")]
+static FREEBSD: &[Entry] = &[
+    Entry::new(Step::Pkg, "FreeBSD Packages", freebsd::upgrade_packages),
+    Entry::new(Step::System, "FreeBSD Upgrade", freebsd::upgrade_freebsd),
+    Entry::new(Step::Audit, "FreeBSD Audit", freebsd::audit_packages),
+];
+
+#[cfg(target_os = "openbsd")]
+static OPENBSD: &[Entry] = &[
+    Entry::new(Step::Pkg, "OpenBSD Packages", openbsd::upgrade_packages),
+    Entry::new(Step::System, "OpenBSD Upgrade", openbsd::upgrade_openbsd),
+];
+
+#[cfg(target_os = "android")]
+static ANDROID: &[Entry] =
+    &[Entry::new(Step::Pkg, "Termux Packages", android::upgrade_packages).needs(&["nala", "pkg"])];
+
+#[cfg(unix)]
+static UNIX: &[Entry] = &[
+    Entry::new(Step::Yadm, "yadm", unix::run_yadm).needs(&["yadm"]),
+    Entry::new(Step::Nix, "nix", unix::run_nix).needs(&["nix"]),
+    Entry::new(Step::Nix, "nix upgrade-nix", unix::run_nix_self_upgrade).needs(&["nix"]),
+    Entry::new(Step::Guix, "guix", unix::run_guix).needs(&["guix"]),
+    Entry::new(Step::HomeManager, "home-manager", unix::run_home_manager).needs(&["home-manager"]),
+    Entry::new(Step::Asdf, "asdf", unix::run_asdf).needs(&["asdf"]),
+    Entry::new(Step::Mise, "mise", unix::run_mise).needs(&["mise"]),
+    Entry::new(Step::Pkgin, "pkgin", unix::run_pkgin).needs(&["pkgin"]),
+    Entry::new(Step::BunPackages, "bun-packages", unix::run_bun_packages).needs(&["bun"]),
+    Entry::new(Step::Shell, "zr", zsh::run_zr).needs(&["zr"]),
+    Entry::new(Step::Shell, "antibody", zsh::run_antibody).needs(&["antibody"]),
+    Entry::new(Step::Shell, "antidote", zsh::run_antidote),
+    Entry::new(Step::Shell, "antigen", zsh::run_antigen),
+    Entry::new(Step::Shell, "zgenom", zsh::run_zgenom),
+    Entry::new(Step::Shell, "zplug", zsh::run_zplug),
+    Entry::new(Step::Shell, "zinit", zsh::run_zinit),
+    Entry::new(Step::Shell, "zi", zsh::run_zi),
+    Entry::new(Step::Shell, "zim", zsh::run_zim),
+    Entry::new(Step::Shell, "oh-my-zsh", zsh::run_oh_my_zsh),
+    Entry::new(Step::Shell, "oh-my-bash", unix::run_oh_my_bash),
+    Entry::new(Step::Shell, "fisher", unix::run_fisher),
+    Entry::new(Step::Shell, "bash-it", unix::run_bashit),
+    Entry::new(Step::Shell, "oh-my-fish", unix::run_oh_my_fish),
+    Entry::new(Step::Shell, "fish-plug", unix::run_fish_plug),
+    Entry::new(Step::Shell, "fundle", unix::run_fundle),
+    Entry::new(Step::Tmux, "tmux", tmux::run_tpm),
+    Entry::new(Step::Tldr, "TLDR", unix::run_tldr).needs(&["tldr"]),
+    Entry::new(Step::Pearl, "pearl", unix::run_pearl).needs(&["pearl"]),
+    #[cfg(not(any(target_os = "macos", target_os = "android")))]
+    Entry::new(
+        Step::GnomeShellExtensions,
+        "Gnome Shell Extensions",
+        unix::upgrade_gnome_extensions,
+    )
+    .needs(&["gdbus"]),
+    Entry::new(Step::Pyenv, "pyenv", unix::run_pyenv).needs(&["pyenv"]),
+    Entry::new(Step::Sdkman, "SDKMAN!", unix::run_sdkman),
+    Entry::new(Step::Rcm, "rcm", unix::run_rcm).needs(&["rcup"]),
+    Entry::new(Step::Maza, "maza", unix::run_maza).needs(&["maza"]),
+];
+
+/// Steps available on every OS.
+static COMMON: &[Entry] = &[
+    #[cfg(not(any(
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )))]
+    Entry::new(Step::Atom, "apm", generic::run_apm).needs(&["apm"]),
+    Entry::new(Step::Fossil, "fossil", generic::run_fossil).needs(&["fossil"]),
+    Entry::new(Step::Elan, "elan", generic::run_elan).needs(&["elan"]),
+    Entry::new(Step::Rye, "rye", generic::run_rye).needs(&["rye"]),
+    Entry::new(Step::Rustup, "rustup", generic::run_rustup).needs(&["rustup"]),
+    Entry::new(Step::Juliaup, "juliaup", generic::run_juliaup).needs(&["juliaup"]),
+    Entry::new(Step::Dotnet, ".NET", generic::run_dotnet_upgrade).needs(&["dotnet"]),
+    Entry::new(Step::Choosenim, "choosenim", generic::run_choosenim).needs(&["choosenim"]),
+    Entry::new(Step::Cargo, "cargo", generic::run_cargo_update)
+        .checked_by(generic::check_cargo)
+        .tracked_by(generic::list_cargo),
+    Entry::new(Step::Flutter, "Flutter", generic::run_flutter_upgrade).needs(&["flutter"]),
+    Entry::new(Step::Go, "go-global-update", go::run_go_global_update),
+    Entry::new(Step::Go, "gup", go::run_go_gup),
+    Entry::new(Step::Emacs, "Emacs", |ctx| emacs::Emacs::new().upgrade(ctx)).needs(&["emacs"]),
+    Entry::new(Step::Opam, "opam", generic::run_opam_update).needs(&["opam"]),
+    Entry::new(Step::Vcpkg, "vcpkg", generic::run_vcpkg_update).needs(&["vcpkg"]),
+    Entry::new(Step::Pipx, "pipx", generic::run_pipx_update)
+        .needs(&["pipx"])
+        .tracked_by(generic::list_pipx),
+    Entry::new(Step::Pipxu, "pipxu", generic::run_pipxu_update).needs(&["pipxu"]),
+    Entry::new(
+        Step::Vscode,
+        "Visual Studio Code extensions",
+        generic::run_vscode_extensions_update,
+    ),
+    Entry::new(
+        Step::Vscodium,
+        "VSCodium extensions",
+        generic::run_vscodium_extensions_update,
+    ),
+    Entry::new(Step::Conda, "conda", generic::run_conda_update).needs(&["conda"]),
+    Entry::new(Step::Mamba, "mamba", generic::run_mamba_update).needs(&["mamba"]),
+    Entry::new(Step::Pixi, "pixi", generic::run_pixi_update).needs(&["pixi"]),
+    Entry::new(Step::Miktex, "miktex", generic::run_miktex_packages_update).needs(&["miktex"]),
+    Entry::new(Step::Pip3, "pip3", generic::run_pip3_update).needs(&["python3", "python"]),
+    Entry::new(Step::PipReview, "pip-review", generic::run_pip_review_update).needs(&["pip-review"]),
+    Entry::new(
+        Step::PipReviewLocal,
+        "pip-review (local)",
+        generic::run_pip_review_local_update,
+    )
+    .needs(&["pip-review"]),
+    Entry::new(Step::Pipupgrade, "pipupgrade", generic::run_pipupgrade_update).needs(&["pipupgrade"]),
+    Entry::new(Step::Ghcup, "ghcup", generic::run_ghcup_update).needs(&["ghcup"]),
+    Entry::new(Step::Stack, "stack", generic::run_stack_update).needs(&["stack"]),
+    Entry::new(Step::Tlmgr, "tlmgr", generic::run_tlmgr_update).needs(&["tlmgr"]),
+    Entry::new(Step::Myrepos, "myrepos", generic::run_myrepos_update).needs(&["mr"]),
+    Entry::new(Step::Chezmoi, "chezmoi", generic::run_chezmoi_update).needs(&["chezmoi"]),
+    Entry::new(Step::Jetpack, "jetpack", generic::run_jetpack).needs(&["jetpack"]),
+    Entry::new(Step::Vim, "vim", vim::upgrade_vim).needs(&["vim"]),
+    Entry::new(Step::Vim, "Neovim", vim::upgrade_neovim).needs(&["nvim"]),
+    Entry::new(Step::Vim, "The Ultimate vimrc", vim::upgrade_ultimate_vimrc),
+    Entry::new(Step::Vim, "voom", vim::run_voom).needs(&["voom"]),
+    Entry::new(Step::Kakoune, "Kakoune", kakoune::upgrade_kak_plug).needs(&["kak"]),
+    Entry::new(Step::Helix, "helix", generic::run_helix_grammars),
+    Entry::new(Step::Node, "npm", node::run_npm_upgrade)
+        .needs(&["npm"])
+        .checked_by(node::check_npm)
+        .tracked_by(node::list_npm),
+    Entry::new(Step::Yarn, "yarn", node::run_yarn_upgrade).needs(&["yarn"]),
+    Entry::new(Step::Pnpm, "pnpm", node::run_pnpm_upgrade).needs(&["pnpm"]),
+    Entry::new(Step::VoltaPackages, "volta packages", node::run_volta_packages_upgrade).needs(&["volta"]),
+    Entry::new(Step::Containers, "Containers", containers::run_containers).detected_by(detect_container_runtime),
+    Entry::new(Step::Deno, "deno", node::deno_upgrade).needs(&["deno"]),
+    Entry::new(Step::Composer, "composer", generic::run_composer_update).needs(&["composer"]),
+    Entry::new(Step::Krew, "krew", generic::run_krew_upgrade).needs(&["kubectl-krew"]),
+    Entry::new(Step::Helm, "helm", generic::run_helm_repo_update).needs(&["helm"]),
+    Entry::new(Step::Gem, "gem", generic::run_gem).needs(&["gem"]),
+    Entry::new(Step::RubyGems, "rubygems", generic::run_rubygems).needs(&["gem"]),
+    Entry::new(Step::Julia, "julia", generic::update_julia_packages).needs(&["julia"]),
+    Entry::new(Step::Haxelib, "haxelib", generic::run_haxelib_update).needs(&["haxelib"]),
+    Entry::new(Step::Sheldon, "sheldon", generic::run_sheldon).needs(&["sheldon"]),
+    Entry::new(Step::Stew, "stew", generic::run_stew).needs(&["stew"]),
+    Entry::new(Step::Rtcl, "rtcl", generic::run_rtcl).needs(&["rupdate"]),
+    Entry::new(Step::Bin, "bin", generic::bin_update).needs(&["bin"]),
+    Entry::new(Step::Gcloud, "gcloud", generic::run_gcloud_components_update).needs(&["gcloud"]),
+    Entry::new(Step::Micro, "micro", generic::run_micro).needs(&["micro"]),
+    Entry::new(Step::Raco, "raco", generic::run_raco_update).needs(&["raco"]),
+    Entry::new(Step::Spicetify, "spicetify", generic::spicetify_upgrade).needs(&["spicetify", "spicetify-cli"]),
+    Entry::new(
+        Step::GithubCliExtensions,
+        "GitHub CLI Extensions",
+        generic::run_ghcli_extensions_upgrade,
+    )
+    .needs(&["gh"]),
+    Entry::new(Step::Bob, "Bob", generic::run_bob).needs(&["bob"]),
+    Entry::new(Step::Certbot, "Certbot", generic::run_certbot).needs(&["certbot"]),
+    Entry::new(Step::GitRepos, "Git Repositories", git::run_git_pull),
+    Entry::new(Step::ClamAvDb, "ClamAV Databases", generic::run_freshclam).needs(&["freshclam"]),
+    Entry::new(Step::PlatformioCore, "PlatformIO Core", generic::run_platform_io),
+    Entry::new(
+        Step::Lensfun,
+        "Lensfun's database update",
+        generic::run_lensfun_update_data,
+    )
+    .needs(&["lensfun-update-data"]),
+    Entry::new(Step::Poetry, "Poetry", generic::run_poetry).needs(&["poetry"]),
+    Entry::new(Step::Uv, "uv", generic::run_uv).needs(&["uv"]),
+    Entry::new(Step::Zvm, "ZVM", generic::run_zvm).needs(&["zvm"]),
+    Entry::new(Step::Aqua, "aqua", generic::run_aqua),
+    Entry::new(Step::Bun, "bun", generic::run_bun).needs(&["bun"]),
+    Entry::new(Step::Zigup, "zigup", generic::run_zigup).needs(&["zigup"]),
+    Entry::new(
+        Step::JetbrainsToolbox,
+        "JetBrains Toolbox",
+        generic::run_jetbrains_toolbox,
+    ),
+    Entry::new(
+        Step::AndroidStudio,
+        "Android Studio plugins",
+        generic::run_android_studio,
+    )
+    .needs(&[
+        "studio",
+        "android-studio",
+        "android-studio-beta",
+        "android-studio-canary",
+    ]),
+    Entry::new(
+        Step::JetbrainsAqua,
+        "JetBrains Aqua plugins",
+        generic::run_jetbrains_aqua,
+    ),
+    Entry::new(
+        Step::JetbrainsClion,
+        "JetBrains CLion plugins",
+        generic::run_jetbrains_clion,
+    )
+    .needs(&["clion", "clion-eap"]),
+    Entry::new(
+        Step::JetbrainsDatagrip,
+        "JetBrains DataGrip plugins",
+        generic::run_jetbrains_datagrip,
+    )
+    .needs(&["datagrip", "datagrip-eap"]),
+    Entry::new(
+        Step::JetbrainsDataspell,
+        "JetBrains DataSpell plugins",
+        generic::run_jetbrains_dataspell,
+    )
+    .needs(&["dataspell", "dataspell-eap"]),
+    Entry::new(
+        Step::JetbrainsGateway,
+        "JetBrains Gateway plugins",
+        generic::run_jetbrains_gateway,
+    )
+    .needs(&["gateway", "jetbrains-gateway", "jetbrains-gateway-eap"]),
+    Entry::new(
+        Step::JetbrainsGoland,
+        "JetBrains GoLand plugins",
+        generic::run_jetbrains_goland,
+    )
+    .needs(&["goland", "goland-eap"]),
+    Entry::new(
+        Step::JetbrainsIdea,
+        "JetBrains IntelliJ IDEA plugins",
+        generic::run_jetbrains_idea,
+    )
+    .needs(&[
+        "idea",
+        "intellij-idea-ultimate-edition",
+        "intellij-idea-community-edition",
+    ]),
+    Entry::new(Step::JetbrainsMps, "JetBrains MPS plugins", generic::run_jetbrains_mps)
+        .needs(&["mps", "jetbrains-mps"]),
+    Entry::new(
+        Step::JetbrainsPhpstorm,
+        "JetBrains PhpStorm plugins",
+        generic::run_jetbrains_phpstorm,
+    )
+    .needs(&["phpstorm"]),
+    Entry::new(
+        Step::JetbrainsPycharm,
+        "JetBrains PyCharm plugins",
+        generic::run_jetbrains_pycharm,
+    )
+    .needs(&["pycharm", "pycharm-professional", "pycharm-eap"]),
+    Entry::new(
+        Step::JetbrainsRider,
+        "JetBrains Rider plugins",
+        generic::run_jetbrains_rider,
+    )
+    .needs(&["rider", "rider-eap"]),
+    Entry::new(
+        Step::JetbrainsRubymine,
+        "JetBrains RubyMine plugins",
+        generic::run_jetbrains_rubymine,
+    )
+    .needs(&["rubymine", "jetbrains-rubymine", "rubymine-eap"]),
+    Entry::new(
+        Step::JetbrainsRustrover,
+        "JetBrains RustRover plugins",
+        generic::run_jetbrains_rustrover,
+    )
+    .needs(&["rustrover", "rustrover-eap"]),
+    Entry::new(
+        Step::JetbrainsWebstorm,
+        "JetBrains WebStorm plugins",
+        generic::run_jetbrains_webstorm,
+    )
+    .needs(&["webstorm", "webstorm-eap"]),
+    Entry::new(Step::Yazi, "Yazi packages", generic::run_yazi).needs(&["ya"]),
+    Entry::new(Step::Powershell, "Powershell Modules Update", update_powershell_modules),
+];
+
+#[cfg(target_os = "linux")]
+fn detect_distribution(_: &ExecutionContext) -> Result<Option<PathBuf>> {
+    linux::Distribution::detect()
+        .map_err(|e| color_eyre::eyre::eyre!(t!("Error detecting current distribution: {error}", error = e)))?;
+    Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+fn upgrade_distribution(ctx: &ExecutionContext) -> Result<()> {
+    linux::Distribution::detect()?.upgrade(ctx)
+}
+
+#[cfg(target_os = "linux")]
+fn check_distribution(_: &ExecutionContext) -> Result<Vec<PackageChange>> {
+    linux::Distribution::detect()?.check_updates()
+}
+
+#[cfg(target_os = "linux")]
+fn list_distribution_packages(_: &ExecutionContext) -> Result<Versions> {
+    linux::Distribution::detect()?.installed_packages()
+}
+
+fn detect_container_runtime(ctx: &ExecutionContext) -> Result<Option<PathBuf>> {
+    require(ctx.config().containers_runtime().to_string()).map(Some)
+}
+
+fn update_powershell_modules(ctx: &ExecutionContext) -> Result<()> {
+    let powershell = powershell::Powershell::new();
+    require_option(powershell.profile(), t!("Powershell is not installed").to_string())?;
+    powershell.update_modules(ctx)
+}
+
+#[derive(Serialize)]
+struct Detected {
+    step: String,
+    name: &'static str,
+    /// `None` if the step only finds out when it runs.
+    applicable: Option<bool>,
+    binary: Option<PathBuf>,
+    reason: Option<String>,
+}
+
+/// Run the detection of every step and print which ones apply to this machine, as a table or as
+/// JSON.
+pub fn print_detection(ctx: &ExecutionContext, json: bool) -> Result<()> {
+    let detected: Vec<_> = entries()
+        .map(|entry| {
+            let (applicable, binary, reason) = if entry.detects() {
+                match entry.detect(ctx) {
+                    Ok(binary) => (Some(true), binary, None),
+                    Err(e) => (Some(false), None, Some(e.to_string())),
+                }
+            } else {
+                (None, None, None)
+            };
+            Detected {
+                step: entry.step.to_string(),
+                name: entry.name,
+                applicable,
+                binary,
+                reason,
+            }
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&detected)?);
+        return Ok(());
+    }
+
+    let header = [t!("Step"), t!("Name"), t!("Applicable"), t!("Details")];
+    let step_width = detected
+        .iter()
+        .map(|d| d.step.len())
+        .chain([header[0].chars().count()])
+        .max()
+        .unwrap();
+    let name_width = detected
+        .iter()
+        .map(|d| d.name.len())
+        .chain([header[1].chars().count()])
+        .max()
+        .unwrap();
+    let applicable = |value: Option<bool>| match value {
+        Some(true) => (t!("yes"), Style::new().green()),
+        Some(false) => (t!("no"), Style::new().red()),
+        None => (t!("unknown"), Style::new().dim()),
+    };
+    let applicable_width = [Some(true), Some(false), None]
+        .into_iter()
+        .map(|value| applicable(value).0.chars().count())
+        .chain([header[2].chars().count()])
+        .max()
+        .unwrap();
+
+    println!(
+        "{}",
+        style(format!(
+            "{:step_width$}  {:name_width$}  {:applicable_width$}  {}",
+            header[0], header[1], header[2], header[3]
+        ))
+        .bold()
+    );
+    for d in &detected {
+        let (value, value_style) = applicable(d.applicable);
+        let details = match (&d.binary, &d.reason) {
+            (Some(binary), _) => binary.display().to_string(),
+            (None, Some(reason)) => reason.clone(),
+            (None, None) => String::new(),
+        };
+        println!(
+            "{:step_width$}  {:name_width$}  {}  {}",
+            d.step,
+            d.name,
+            value_style.apply_to(format!("{value:applicable_width$}")),
+            details
+        );
+    }
+
+    Ok(())
+}
@@ -1,8 +1,25 @@
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write as _};
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{SnapshotTool, Step};
+use crate::utils::hostname;
 
 pub enum StepResult {
     Success,
     Failure,
+    TimedOut,
     Ignored,
     Skipped(String),
 }
@@ -11,35 +28,481 @@ impl StepResult {
     pub fn failed(&self) -> bool {
         match self {
             StepResult::Success | StepResult::Ignored | StepResult::Skipped(_) => false,
-            StepResult::Failure => true,
+            StepResult::Failure | StepResult::TimedOut => true,
+        }
+    }
+
+    /// How the result is named in the JSON report and in the environment of hooks.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StepResult::Success => "success",
+            StepResult::Failure => "failure",
+            StepResult::TimedOut => "timed_out",
+            StepResult::Ignored => "ignored",
+            StepResult::Skipped(_) => "skipped",
+        }
+    }
+
+    /// The inverse of `name`, for reading reports back.
+    pub fn from_name(name: &str, skip_reason: Option<String>) -> Option<Self> {
+        match name {
+            "success" => Some(StepResult::Success),
+            "failure" => Some(StepResult::Failure),
+            "timed_out" => Some(StepResult::TimedOut),
+            "ignored" => Some(StepResult::Ignored),
+            "skipped" => Some(StepResult::Skipped(skip_reason.unwrap_or_default())),
+            _ => None,
+        }
+    }
+}
+
+/// A package whose version changed during a step, or that can be upgraded when only checking for
+/// updates.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageChange {
+    pub name: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl PackageChange {
+    pub fn new(name: impl Into<String>, from: Option<String>, to: Option<String>) -> Self {
+        Self {
+            name: name.into(),
+            from,
+            to,
+        }
+    }
+}
+
+impl Display for PackageChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        match (&self.from, &self.to) {
+            (Some(from), Some(to)) => write!(f, ": {from} -> {to}"),
+            (None, Some(to)) => write!(f, ": {to}"),
+            (Some(from), None) => write!(f, ": {from}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// The installed versions of packages, by name.
+pub type Versions = BTreeMap<String, String>;
+
+/// Parse a listing of installed packages with a `name version` pair on each line, ignoring
+/// anything after the version.
+pub fn parse_versions(listing: &str) -> Versions {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?.to_string(), fields.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// The packages that were installed, upgraded, downgraded or removed between two listings.
+pub fn diff_versions(before: &Versions, after: &Versions) -> Vec<PackageChange> {
+    let changed = after
+        .iter()
+        .filter(|(name, to)| before.get(*name) != Some(to))
+        .map(|(name, to)| PackageChange::new(name, before.get(name).cloned(), Some(to.clone())));
+    let removed = before
+        .iter()
+        .filter(|(name, _)| !after.contains_key(*name))
+        .map(|(name, from)| PackageChange::new(name, Some(from.clone()), None));
+
+    let mut changes: Vec<_> = changed.chain(removed).collect();
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    changes
+}
+
+/// Whether a snapshot was taken before or after the upgrade.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, strum::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SnapshotPhase {
+    Pre,
+    Post,
+}
+
+/// A filesystem snapshot taken by a step, to roll back its changes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub tool: SnapshotTool,
+    pub phase: SnapshotPhase,
+    /// What identifies the snapshot for its tool: a number, a name or a path.
+    pub id: String,
+}
+
+impl Snapshot {
+    /// The command rolling back to the snapshot, if the tool can do it by itself.
+    pub fn rollback_command(&self) -> Option<String> {
+        match self.tool {
+            SnapshotTool::Snapper => Some(format!("sudo snapper rollback {}", self.id)),
+            SnapshotTool::Timeshift => Some(format!("sudo timeshift --restore --snapshot '{}'", self.id)),
+            SnapshotTool::Zfs => Some(format!("sudo zfs rollback -r {}", self.id)),
+            SnapshotTool::Btrfs => None,
         }
     }
 }
 
+impl Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.tool, self.phase, self.id)
+    }
+}
+
+thread_local! {
+    /// Package changes recorded by the step running on this thread.
+    static PACKAGES: RefCell<Vec<PackageChange>> = const { RefCell::new(Vec::new()) };
+
+    /// Snapshots taken by the step running on this thread.
+    static SNAPSHOTS: RefCell<Vec<Snapshot>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Record package changes of the step running on the current thread, they end up in its report.
+pub fn record_packages(changes: impl IntoIterator<Item = PackageChange>) {
+    PACKAGES.with_borrow_mut(|packages| packages.extend(changes));
+}
+
+/// Run `f`, returning the package changes it recorded with `record_packages`.
+pub fn collect_packages<T>(f: impl FnOnce() -> T) -> (T, Vec<PackageChange>) {
+    PACKAGES.with_borrow_mut(Vec::clear);
+    let result = f();
+    (result, PACKAGES.with_borrow_mut(mem::take))
+}
+
+/// Record a snapshot taken by the step running on the current thread, it ends up in its report.
+pub fn record_snapshot(snapshot: Snapshot) {
+    SNAPSHOTS.with_borrow_mut(|snapshots| snapshots.push(snapshot));
+}
+
+/// Run `f`, returning the snapshots it recorded with `record_snapshot`.
+pub fn collect_snapshots<T>(f: impl FnOnce() -> T) -> (T, Vec<Snapshot>) {
+    SNAPSHOTS.with_borrow_mut(Vec::clear);
+    let result = f();
+    (result, SNAPSHOTS.with_borrow_mut(mem::take))
+}
+
 type CowString<'a> = Cow<'a, str>;
-type ReportData<'a> = Vec<(CowString<'a>, StepResult)>;
+
+/// The outcome of a single executed step.
+pub struct StepReport<'a> {
+    pub key: CowString<'a>,
+    pub step: Step,
+    pub result: StepResult,
+    /// The error chain of the last failed attempt, outermost error first.
+    pub errors: Vec<String>,
+    /// Packages upgraded by the last attempt, or that can be upgraded when checking for updates.
+    pub packages: Vec<PackageChange>,
+    /// Snapshots taken by the step, by all its attempts.
+    pub snapshots: Vec<Snapshot>,
+    /// How many times the step ran.
+    pub attempts: u32,
+    pub started_at: DateTime<Local>,
+    pub finished_at: DateTime<Local>,
+}
+
+impl StepReport<'_> {
+    pub fn duration(&self) -> Duration {
+        (self.finished_at - self.started_at).to_std().unwrap_or_default()
+    }
+}
+
+type ReportData<'a> = Vec<StepReport<'a>>;
 pub struct Report<'a> {
+    started_at: DateTime<Local>,
     data: ReportData<'a>,
+    /// Why the machine has to be rebooted after the run, if it has to.
+    reboot_required: Option<String>,
 }
 
 impl<'a> Report<'a> {
-    pub fn new() -> Self {
-        Self { data: Vec::new() }
+    pub fn new(started_at: DateTime<Local>) -> Self {
+        Self {
+            started_at,
+            data: Vec::new(),
+            reboot_required: None,
+        }
     }
 
-    pub fn push_result<M>(&mut self, result: Option<(M, StepResult)>)
-    where
-        M: Into<CowString<'a>>,
-    {
-        if let Some((key, success)) = result {
-            let key = key.into();
-
-            debug_assert!(!self.data.iter().any(|(k, _)| k == &key), "{key} already reported");
-            self.data.push((key, success));
-        }
+    pub fn push_result(&mut self, result: StepReport<'a>) {
+        debug_assert!(
+            !self.data.iter().any(|r| r.step == result.step && r.key == result.key),
+            "{} already reported",
+            result.key
+        );
+        self.data.push(result);
     }
 
     pub fn data(&self) -> &ReportData<'a> {
         &self.data
     }
+
+    pub fn started_at(&self) -> DateTime<Local> {
+        self.started_at
+    }
+
+    pub fn reboot_required(&self) -> Option<&str> {
+        self.reboot_required.as_deref()
+    }
+
+    pub fn set_reboot_required(&mut self, reason: Option<String>) {
+        self.reboot_required = reason;
+    }
+
+    /// Serialize the report in the given `format` and write it to `path`, or to stdout if no
+    /// path is given.
+    pub fn write(&self, format: ReportFormat, path: Option<&Path>) -> Result<()> {
+        let finished_at = Local::now();
+        let contents = match format {
+            ReportFormat::Json => self.to_json(finished_at)?,
+            ReportFormat::Junit => self.to_junit(finished_at),
+        };
+
+        match path {
+            Some(path) => {
+                fs::write(path, contents).with_context(|| format!("Failed to write the report to {}", path.display()))
+            }
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(contents.as_bytes())?;
+                stdout.write_all(b"\n")?;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn to_json(&self, finished_at: DateTime<Local>) -> Result<String> {
+        let report = JsonReport {
+            version: env!("CARGO_PKG_VERSION"),
+            hostname: hostname().ok(),
+            started_at: self.started_at.to_rfc3339(),
+            finished_at: finished_at.to_rfc3339(),
+            duration: seconds(&(finished_at - self.started_at).to_std().unwrap_or_default()),
+            reboot_required: self.reboot_required.is_some(),
+            reboot_reason: self.reboot_required.as_deref(),
+            steps: self
+                .data
+                .iter()
+                .map(|r| JsonStep {
+                    key: &r.key,
+                    step: r.step.to_string(),
+                    result: r.result.name(),
+                    skip_reason: match &r.result {
+                        StepResult::Skipped(reason) => Some(reason),
+                        _ => None,
+                    },
+                    errors: &r.errors,
+                    packages: &r.packages,
+                    snapshots: &r.snapshots,
+                    attempts: r.attempts,
+                    started_at: r.started_at.to_rfc3339(),
+                    finished_at: r.finished_at.to_rfc3339(),
+                    duration: seconds(&r.duration()),
+                })
+                .collect(),
+        };
+
+        Ok(serde_json::to_string_pretty(&report)?)
+    }
+
+    fn to_junit(&self, finished_at: DateTime<Local>) -> String {
+        let count = |f: fn(&StepResult) -> bool| self.data.iter().filter(|r| f(&r.result)).count();
+        let tests = self.data.len();
+        let failures = count(StepResult::failed);
+        let skipped = count(|r| matches!(r, StepResult::Skipped(_) | StepResult::Ignored));
+        let time = seconds(&(finished_at - self.started_at).to_std().unwrap_or_default());
+
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuites name=\"topgrade\" tests=\"{tests}\" failures=\"{failures}\" skipped=\"{skipped}\" time=\"{time:.3}\">"
+        );
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"topgrade\" hostname=\"{}\" timestamp=\"{}\" tests=\"{tests}\" failures=\"{failures}\" errors=\"0\" skipped=\"{skipped}\" time=\"{time:.3}\">",
+            xml_escape(&hostname().unwrap_or_default()),
+            self.started_at.to_rfc3339(),
+        );
+
+        for r in &self.data {
+            let _ = write!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"topgrade.{}\" time=\"{:.3}\"",
+                xml_escape(&r.key),
+                r.step,
+                seconds(&r.duration())
+            );
+            let errors = xml_escape(&r.errors.join("\n"));
+            match &r.result {
+                StepResult::Success => xml.push_str("/>\n"),
+                StepResult::Failure | StepResult::TimedOut => {
+                    let message = xml_escape(r.errors.first().map_or("", String::as_str));
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <failure message=\"{message}\">{errors}</failure>\n    </testcase>"
+                    );
+                }
+                StepResult::Ignored => {
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <skipped message=\"failure ignored\"/>\n      <system-err>{errors}</system-err>\n    </testcase>"
+                    );
+                }
+                StepResult::Skipped(reason) => {
+                    let _ = writeln!(
+                        xml,
+                        ">\n      <skipped message=\"{}\"/>\n    </testcase>",
+                        xml_escape(reason)
+                    );
+                }
+            }
+        }
+
+        xml.push_str("  </testsuite>\n</testsuites>");
+        xml
+    }
+}
+
+/// The format of the machine-readable report written at the end of a run.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+#[clap(rename_all = "snake_case")]
+pub enum ReportFormat {
+    Json,
+    Junit,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'r> {
+    version: &'static str,
+    hostname: Option<String>,
+    started_at: String,
+    finished_at: String,
+    duration: f64,
+    reboot_required: bool,
+    reboot_reason: Option<&'r str>,
+    steps: Vec<JsonStep<'r>>,
+}
+
+#[derive(Serialize)]
+struct JsonStep<'r> {
+    key: &'r str,
+    step: String,
+    result: &'static str,
+    skip_reason: Option<&'r String>,
+    errors: &'r Vec<String>,
+    packages: &'r Vec<PackageChange>,
+    snapshots: &'r Vec<Snapshot>,
+    attempts: u32,
+    started_at: String,
+    finished_at: String,
+    duration: f64,
+}
+
+fn seconds(duration: &Duration) -> f64 {
+    duration.as_secs_f64()
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if c.is_control() && c != '\n' && c != '\t' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report() -> Report<'static> {
+        let now = Local::now();
+        let mut report = Report::new(now);
+        report.push_result(StepReport {
+            key: "cargo".into(),
+            step: Step::Cargo,
+            result: StepResult::Success,
+            errors: Vec::new(),
+            packages: vec![PackageChange::new(
+                "ripgrep",
+                Some("14.0.3".to_string()),
+                Some("14.1.0".to_string()),
+            )],
+            snapshots: Vec::new(),
+            attempts: 1,
+            started_at: now,
+            finished_at: now + chrono::Duration::seconds(2),
+        });
+        report.push_result(StepReport {
+            key: "Remote (<host>)".into(),
+            step: Step::Remotes,
+            result: StepResult::Failure,
+            errors: vec!["Command failed: `ssh`".to_string(), "exit status: 1".to_string()],
+            packages: Vec::new(),
+            snapshots: vec![Snapshot {
+                tool: SnapshotTool::Snapper,
+                phase: SnapshotPhase::Pre,
+                id: "42".to_string(),
+            }],
+            attempts: 3,
+            started_at: now,
+            finished_at: now,
+        });
+        report
+    }
+
+    #[test]
+    fn json_report_contains_every_step() {
+        let json: serde_json::Value = serde_json::from_str(&report().to_json(Local::now()).unwrap()).unwrap();
+        let steps = json["steps"].as_array().unwrap();
+
+        assert_eq!(json["reboot_required"], false);
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0]["step"], "cargo");
+        assert_eq!(steps[0]["result"], "success");
+        assert_eq!(steps[0]["duration"], 2.0);
+        assert_eq!(steps[0]["packages"][0]["name"], "ripgrep");
+        assert_eq!(steps[0]["packages"][0]["to"], "14.1.0");
+        assert_eq!(steps[1]["step"], "remotes");
+        assert_eq!(steps[1]["errors"][1], "exit status: 1");
+        assert_eq!(steps[1]["attempts"], 3);
+        assert_eq!(steps[1]["snapshots"][0]["tool"], "snapper");
+        assert_eq!(steps[1]["snapshots"][0]["phase"], "pre");
+        assert_eq!(steps[1]["snapshots"][0]["id"], "42");
+    }
+
+    #[test]
+    fn diff_versions_lists_every_change() {
+        let before = parse_versions("bat 0.24.0\nfd 9.0.0\nripgrep 14.0.3\n");
+        let after = parse_versions("bat 0.24.0\nripgrep 14.1.0 (extra)\nzoxide 0.9.4\n");
+
+        assert_eq!(
+            diff_versions(&before, &after),
+            vec![
+                PackageChange::new("fd", Some("9.0.0".to_string()), None),
+                PackageChange::new("ripgrep", Some("14.0.3".to_string()), Some("14.1.0".to_string())),
+                PackageChange::new("zoxide", None, Some("0.9.4".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn junit_report_is_escaped() {
+        let xml = report().to_junit(Local::now());
+
+        assert!(xml.contains("tests=\"2\" failures=\"1\""));
+        assert!(xml.contains("<testcase name=\"Remote (&lt;host&gt;)\" classname=\"topgrade.remotes\""));
+        assert!(xml.contains("<failure message=\"Command failed: `ssh`\">"));
+    }
 }
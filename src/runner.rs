@@ -1,67 +1,440 @@
+use crate::command::{keep_terminal, record_stderr, with_timeout, CommandExt};
+use crate::config::{Hook, StepKind};
 use crate::ctrlc;
-use crate::error::{DryRun, SkipStep};
+use crate::error::{DryRun, SkipStep, StepTimedOut};
 use crate::execution_context::ExecutionContext;
-use crate::report::{Report, StepResult};
-use crate::terminal::print_error;
+use crate::history::{self, StepKey};
+use crate::log_file;
+use crate::notify;
+use crate::report::{collect_packages, collect_snapshots, PackageChange, Report, Snapshot, StepReport, StepResult};
+use crate::terminal::{capture_output, print_error, print_warning, shell};
+use crate::utils::format_duration;
 use crate::{config::Step, terminal::should_retry};
-use color_eyre::eyre::Result;
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{bail, Result};
+use rust_i18n::t;
 use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fmt::Debug;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
 use tracing::debug;
 
+type StepFn<'a> = Box<dyn Fn() -> Result<()> + Send + Sync + 'a>;
+
+/// The steps whose pre hook ran, with the error of the hook if it failed.
+type PreHooks = Mutex<Vec<(Step, Result<(), String>)>>;
+
+/// A step waiting to be run concurrently with other steps.
+struct Queued<'a> {
+    step: Step,
+    key: Cow<'a, str>,
+    func: StepFn<'a>,
+    detect: Option<StepFn<'a>>,
+}
+
+/// A step added to the plan, which is sorted before running it.
+pub struct Planned<'a> {
+    step: Step,
+    key: Cow<'a, str>,
+    func: StepFn<'a>,
+    before: Vec<Step>,
+    detect: Option<StepFn<'a>>,
+}
+
+impl<'a> Planned<'a> {
+    /// Run this entry before all the entries of the given steps.
+    pub fn before(&mut self, steps: &[Step]) -> &mut Self {
+        self.before.extend(steps);
+        self
+    }
+
+    /// Find out with `detect` whether this entry applies before running it, so that the pre hook
+    /// of the step does not run for an entry that is skipped. `detect` fails with `SkipStep` if
+    /// the entry does not apply, or with another error if it cannot tell, which is printed as a
+    /// warning before the entry is skipped.
+    pub fn detected_by(&mut self, detect: impl Fn() -> Result<()> + Send + Sync + 'a) -> &mut Self {
+        self.detect = Some(Box::new(detect));
+        self
+    }
+}
+
+/// A single run of a step.
+struct Attempt {
+    started_at: DateTime<Local>,
+    result: Result<()>,
+    timed_out: bool,
+    packages: Vec<PackageChange>,
+    snapshots: Vec<Snapshot>,
+    /// What the commands of the step wrote to their standard error, if it was recorded.
+    stderr: String,
+}
+
+impl Attempt {
+    /// Run `func` in the span of the step, killing its commands after `timeout`. With `record`, the
+    /// standard error of the commands of an unattended step is recorded; the commands of the other
+    /// steps keep the terminal for their prompts.
+    fn run(step: Step, key: &str, func: &dyn Fn() -> Result<()>, timeout: Option<Duration>, record: bool) -> Self {
+        let started_at = Local::now();
+        let unattended = step.kind() == StepKind::Unattended;
+        let ((((result, timed_out), stderr), packages), snapshots) = collect_snapshots(|| {
+            collect_packages(|| {
+                record_stderr(record && unattended, || {
+                    keep_terminal(!unattended, || {
+                        with_timeout(timeout, unattended, || in_span(step, key, func))
+                    })
+                })
+            })
+        });
+        Attempt {
+            started_at,
+            result,
+            timed_out,
+            packages,
+            snapshots,
+            stderr,
+        }
+    }
+
+    /// Run the detection of the step if it has one, then the pre hook of the step and, unless it
+    /// fails, the step itself.
+    #[allow(clippy::too_many_arguments)]
+    fn first(
+        ctx: &ExecutionContext,
+        pre_hooks: &PreHooks,
+        step: Step,
+        key: &str,
+        func: &dyn Fn() -> Result<()>,
+        detect: Option<&(dyn Fn() -> Result<()> + Send + Sync)>,
+        timeout: Option<Duration>,
+        record: bool,
+    ) -> Self {
+        let started_at = Local::now();
+        let detected = detect.map_or(Ok(()), |detect| detect()).map_err(|e| {
+            if e.is::<SkipStep>() {
+                return e;
+            }
+            print_warning(e.to_string());
+            SkipStep(e.to_string()).into()
+        });
+        let result = detected.and_then(|()| {
+            run_pre_hook(ctx, pre_hooks, step, key)
+                .map_err(|e| SkipStep(t!("The pre hook failed: {error}", error = e).to_string()).into())
+        });
+        match result {
+            Ok(()) => Self::run(step, key, func, timeout, record),
+            Err(e) => Attempt {
+                started_at,
+                result: Err(e),
+                timed_out: false,
+                packages: Vec::new(),
+                snapshots: Vec::new(),
+                stderr: String::new(),
+            },
+        }
+    }
+}
+
 pub struct Runner<'a> {
     ctx: &'a ExecutionContext<'a>,
     report: Report<'a>,
+    queue: Vec<Queued<'a>>,
+    plan: Vec<Planned<'a>>,
+    /// Steps done by the interrupted run that this one resumes.
+    resumed: Vec<StepKey>,
+    /// The only steps to run, if not all of them.
+    only: Option<Vec<StepKey>>,
+    /// Whether to save the progress of the run after every step, so that it can be resumed.
+    save_progress: bool,
+    pre_hooks: PreHooks,
 }
 
 impl<'a> Runner<'a> {
     pub fn new(ctx: &'a ExecutionContext) -> Runner<'a> {
         Runner {
             ctx,
-            report: Report::new(),
+            report: Report::new(ctx.started_at()),
+            queue: Vec::new(),
+            plan: Vec::new(),
+            resumed: Vec::new(),
+            only: None,
+            save_progress: !(ctx.config().dry_run() || ctx.config().check()),
+            pre_hooks: Mutex::new(Vec::new()),
         }
     }
 
+    /// Skip the steps that the interrupted run being resumed already did.
+    pub fn resume(&mut self, done: Vec<StepKey>) {
+        self.resumed = done;
+    }
+
+    /// Only run these steps, e.g. a single remote or custom command, and ignore all the others.
+    pub fn only(&mut self, steps: Vec<StepKey>) {
+        self.only = Some(steps);
+    }
+
+    /// Add a step to the plan. Planned steps only run when calling `run_plan`.
+    pub fn plan<F, M>(&mut self, step: Step, key: M, func: F) -> &mut Planned<'a>
+    where
+        F: Fn() -> Result<()> + Send + Sync + 'a,
+        M: Into<Cow<'a, str>>,
+    {
+        self.plan.push(Planned {
+            step,
+            key: key.into(),
+            func: Box::new(func),
+            before: Vec::new(),
+            detect: None,
+        });
+        self.plan.last_mut().unwrap()
+    }
+
+    /// Run all the planned steps, sorted so that every ordering constraint holds. Steps keep the
+    /// order in which they were planned unless a constraint requires otherwise.
+    ///
+    /// Fails without running anything if the constraints contain a cycle.
+    pub fn run_plan(&mut self) -> Result<()> {
+        let plan = mem::take(&mut self.plan);
+        let mut order = Step::ORDERING.to_vec();
+        order.extend(self.ctx.config().step_order());
+
+        let sorted = sort(&plan, &order)?;
+        let mut plan: Vec<_> = plan.into_iter().map(Some).collect();
+        for index in sorted {
+            let planned = plan[index].take().unwrap();
+            self.schedule(planned.step, planned.key, planned.func, planned.detect)?;
+        }
+
+        self.flush()
+    }
+
+    // Only the self update runs outside of the plan.
+    #[cfg_attr(not(feature = "self-update"), allow(dead_code))]
     pub fn execute<F, M>(&mut self, step: Step, key: M, func: F) -> Result<()>
     where
-        F: Fn() -> Result<()>,
+        F: Fn() -> Result<()> + Send + Sync + 'a,
         M: Into<Cow<'a, str>> + Debug,
     {
+        self.schedule(step, key.into(), Box::new(func), None)
+    }
+
+    /// Run a step now, or queue it to run concurrently with the next ones.
+    fn schedule(&mut self, step: Step, key: Cow<'a, str>, func: StepFn<'a>, detect: Option<StepFn<'a>>) -> Result<()> {
         if !self.ctx.config().should_run(step) {
             return Ok(());
         }
 
-        let key = key.into();
-        debug!("Step {:?}", key);
+        if self
+            .only
+            .as_ref()
+            .is_some_and(|only| !only.iter().any(|s| s.step == step && s.key == key))
+        {
+            return Ok(());
+        }
 
-        // alter the `func` to put it in a span
-        let func = || {
-            let span =
-                tracing::span!(parent: tracing::Span::none(), tracing::Level::TRACE, "step", step = ?step, key = %key);
-            let _guard = span.enter();
-            func()
-        };
+        if self.resumed.iter().any(|done| done.step == step && done.key == key) {
+            debug!("Step {:?} was done by the resumed run", key);
+            let now = Local::now();
+            self.report.push_result(StepReport {
+                key,
+                step,
+                result: StepResult::Skipped(t!("Done by the interrupted run").to_string()),
+                errors: Vec::new(),
+                packages: Vec::new(),
+                snapshots: Vec::new(),
+                attempts: 0,
+                started_at: now,
+                finished_at: now,
+            });
+            return Ok(());
+        }
 
-        loop {
-            match func() {
-                Ok(()) => {
-                    self.report.push_result(Some((key, StepResult::Success)));
-                    break;
-                }
-                Err(e) if e.downcast_ref::<DryRun>().is_some() => break,
-                Err(e) if e.downcast_ref::<SkipStep>().is_some() => {
-                    if self.ctx.config().verbose() || self.ctx.config().show_skipped() {
-                        self.report.push_result(Some((key, StepResult::Skipped(e.to_string()))));
+        // Unattended steps are queued and run together as soon as a step that cannot run
+        // concurrently shows up, which keeps the overall order of the steps.
+        if self.ctx.config().parallelism() > 1 && step.parallelizable() {
+            if self.queue.iter().any(|queued| queued.step.conflicts_with(step)) {
+                self.flush()?;
+            }
+            debug!("Step {:?} queued", key);
+            self.queue.push(Queued {
+                step,
+                key,
+                func,
+                detect,
+            });
+            return Ok(());
+        }
+
+        self.flush()?;
+        self.run(step, key, &func, detect.as_deref(), None)
+    }
+
+    /// Run all queued steps on a pool of `parallelism` threads.
+    ///
+    /// The output of every step is buffered and printed in the original order of the steps. Failed
+    /// steps are offered to be retried once the whole batch has finished.
+    pub fn flush(&mut self) -> Result<()> {
+        let queue = mem::take(&mut self.queue);
+        if queue.is_empty() {
+            return Ok(());
+        }
+
+        let workers = self.ctx.config().parallelism().min(queue.len());
+        debug!("Running {} steps on {} threads", queue.len(), workers);
+
+        let mut attempts = Vec::with_capacity(queue.len());
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+
+            for _ in 0..workers {
+                let sender = sender.clone();
+                let (queue, next, ctx, pre_hooks) = (&queue, &next, self.ctx, &self.pre_hooks);
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(queued) = queue.get(index) else {
+                        break;
+                    };
+
+                    let (attempt, output) = capture_output(|| {
+                        Attempt::first(
+                            ctx,
+                            pre_hooks,
+                            queued.step,
+                            &queued.key,
+                            &*queued.func,
+                            queued.detect.as_deref(),
+                            ctx.config().step_timeout(queued.step),
+                            ctx.config().auto_retry(queued.step).is_some(),
+                        )
+                    });
+                    if sender.send((index, attempt, output)).is_err() {
+                        break;
                     }
-                    break;
+                });
+            }
+            drop(sender);
+
+            // Print the output of each step as soon as it and all the steps before it are done.
+            let mut done: Vec<Option<(Attempt, String)>> = queue.iter().map(|_| None).collect();
+            for (index, attempt, output) in receiver {
+                done[index] = Some((attempt, output));
+                while let Some(Some(_)) = done.get(attempts.len()) {
+                    let (attempt, output) = done[attempts.len()].take().unwrap();
+                    print!("{output}");
+                    log_file::write(&output);
+                    attempts.push(attempt);
                 }
+            }
+        });
+
+        for (queued, attempt) in queue.into_iter().zip(attempts) {
+            self.run(queued.step, queued.key, &queued.func, None, Some(attempt))?;
+        }
+
+        Ok(())
+    }
+
+    /// Run a step, retrying it if it fails with a transient error or asking the user whether to
+    /// retry it otherwise. If `attempt` is given, the step already ran once and its result is used
+    /// instead of the first run.
+    fn run(
+        &mut self,
+        step: Step,
+        key: Cow<'a, str>,
+        func: &dyn Fn() -> Result<()>,
+        detect: Option<&(dyn Fn() -> Result<()> + Send + Sync)>,
+        mut attempt: Option<Attempt>,
+    ) -> Result<()> {
+        debug!("Step {:?}", key);
+
+        let timeout = self.ctx.config().step_timeout(step);
+        let auto_retry = self.ctx.config().auto_retry(step);
+        let mut started_at = None;
+        let mut attempts = 0;
+        let mut errors = Vec::new();
+        let mut packages;
+        let mut snapshots = Vec::new();
+        let result = loop {
+            let Attempt {
+                started_at: attempt_started_at,
+                result,
+                timed_out,
+                packages: attempt_packages,
+                snapshots: attempt_snapshots,
+                stderr,
+            } = attempt.take().unwrap_or_else(|| {
+                if attempts == 0 {
+                    Attempt::first(
+                        self.ctx,
+                        &self.pre_hooks,
+                        step,
+                        &key,
+                        func,
+                        detect,
+                        timeout,
+                        auto_retry.is_some(),
+                    )
+                } else {
+                    Attempt::run(step, &key, func, timeout, auto_retry.is_some())
+                }
+            });
+            started_at.get_or_insert(attempt_started_at);
+            packages = attempt_packages;
+            snapshots.extend(attempt_snapshots);
+            attempts += 1;
+
+            // The commands of the step were killed, even if the step went on without them. It is
+            // not retried, as it would most likely hang again.
+            if timed_out {
+                let error = result
+                    .err()
+                    .unwrap_or_else(|| StepTimedOut(timeout.unwrap_or_default()).into());
+                debug!("Step {:?} timed out: {:?}", key, error);
+                print_error(&key, format!("{error}\n"));
+                errors = error.chain().map(ToString::to_string).collect();
+                break if self.ctx.config().ignore_failure(step) {
+                    StepResult::Ignored
+                } else {
+                    StepResult::TimedOut
+                };
+            }
+
+            match result {
+                Ok(()) => break StepResult::Success,
+                Err(e) if e.downcast_ref::<DryRun>().is_some() => return Ok(()),
+                Err(e) if e.downcast_ref::<SkipStep>().is_some() => break StepResult::Skipped(e.to_string()),
                 Err(e) => {
                     debug!("Step {:?} failed: {:?}", key, e);
+                    errors = e.chain().map(ToString::to_string).collect();
                     let interrupted = ctrlc::interrupted();
                     if interrupted {
                         ctrlc::unset_interrupted();
                     }
 
+                    let transient = auto_retry.as_ref().filter(|auto_retry| {
+                        !interrupted
+                            && attempts < auto_retry.attempts()
+                            && (auto_retry.is_transient(&stderr) || errors.iter().any(|e| auto_retry.is_transient(e)))
+                    });
+                    if let Some(auto_retry) = transient {
+                        let backoff = auto_retry.backoff(attempts + 1);
+                        print_error(&key, format!("{e}\n"));
+                        print_warning(t!(
+                            "Retrying {step_name} in {backoff} (attempt {attempt} of {attempts})",
+                            step_name = key,
+                            backoff = format_duration(backoff),
+                            attempt = attempts + 1,
+                            attempts = auto_retry.attempts()
+                        ));
+                        thread::sleep(backoff);
+                        continue;
+                    }
+
                     let ignore_failure = self.ctx.config().ignore_failure(step);
                     let should_ask = interrupted || !(self.ctx.config().no_retry() || ignore_failure);
                     let should_retry = if should_ask {
@@ -72,18 +445,55 @@ impl<'a> Runner<'a> {
                     };
 
                     if !should_retry {
-                        self.report.push_result(Some((
-                            key,
-                            if ignore_failure {
-                                StepResult::Ignored
-                            } else {
-                                StepResult::Failure
-                            },
-                        )));
-                        break;
+                        break if ignore_failure {
+                            StepResult::Ignored
+                        } else {
+                            StepResult::Failure
+                        };
                     }
                 }
             }
+        };
+
+        if !matches!(result, StepResult::Skipped(_)) {
+            let hooks = if matches!(result, StepResult::Success) {
+                &[Hook::Post][..]
+            } else {
+                &[Hook::OnFailure, Hook::Post]
+            };
+            for &hook in hooks {
+                if let Err(e) = run_hook(self.ctx, hook, step, &key, Some((&result, &errors))) {
+                    print_warning(t!(
+                        "The {hook} hook of {step_name} failed: {error}",
+                        hook = hook,
+                        step_name = key,
+                        error = e
+                    ));
+                }
+            }
+        }
+
+        self.report.push_result(StepReport {
+            key,
+            step,
+            result,
+            errors,
+            packages,
+            snapshots,
+            attempts,
+            started_at: started_at.unwrap_or_else(Local::now),
+            finished_at: Local::now(),
+        });
+
+        let r = self.report.data().last().unwrap();
+        if r.result.failed() {
+            notify::notify_failed_step(self.ctx.config(), r);
+        }
+
+        if self.save_progress {
+            if let Err(e) = history::save_progress(&self.report, &self.resumed) {
+                debug!("Failed to save the progress of the run: {e:?}");
+            }
         }
 
         Ok(())
@@ -92,4 +502,187 @@ impl<'a> Runner<'a> {
     pub fn report(&self) -> &Report {
         &self.report
     }
+
+    pub fn report_mut(&mut self) -> &mut Report<'a> {
+        &mut self.report
+    }
+}
+
+/// Topologically sort the planned steps. In each pair of `order`, all the entries of the first
+/// step have to run before all the entries of the second one. Among the entries that are ready to
+/// run, the one planned first always comes first.
+fn sort(plan: &[Planned], order: &[(Step, Step)]) -> Result<Vec<usize>> {
+    let mut successors = vec![Vec::new(); plan.len()];
+    let mut predecessors = vec![Vec::new(); plan.len()];
+    let mut add_edge = |from: usize, to: usize| {
+        if from != to {
+            successors[from].push(to);
+            predecessors[to].push(from);
+        }
+    };
+    let entries_of = |step: Step| {
+        plan.iter()
+            .enumerate()
+            .filter(move |(_, p)| p.step == step)
+            .map(|(i, _)| i)
+    };
+
+    for (i, planned) in plan.iter().enumerate() {
+        for &step in &planned.before {
+            entries_of(step).for_each(|j| add_edge(i, j));
+        }
+    }
+    for &(first, then) in order {
+        for i in entries_of(first) {
+            entries_of(then).for_each(|j| add_edge(i, j));
+        }
+    }
+
+    let mut waiting_for: Vec<usize> = predecessors.iter().map(Vec::len).collect();
+    let mut ready: BinaryHeap<_> = (0..plan.len()).filter(|&i| waiting_for[i] == 0).map(Reverse).collect();
+    let mut sorted = Vec::with_capacity(plan.len());
+    while let Some(Reverse(i)) = ready.pop() {
+        sorted.push(i);
+        for &j in &successors[i] {
+            waiting_for[j] -= 1;
+            if waiting_for[j] == 0 {
+                ready.push(Reverse(j));
+            }
+        }
+    }
+
+    if sorted.len() < plan.len() {
+        // Every entry left is waiting for another one that is left, so walking back through them
+        // eventually comes back to an entry already seen.
+        let mut path = vec![(0..plan.len()).find(|&i| waiting_for[i] > 0).unwrap()];
+        loop {
+            let last = *path.last().unwrap();
+            let previous = *predecessors[last].iter().find(|&&i| waiting_for[i] > 0).unwrap();
+            if let Some(start) = path.iter().position(|&i| i == previous) {
+                let mut cycle: Vec<_> = path[start..].iter().rev().map(|&i| plan[i].key.as_ref()).collect();
+                cycle.push(cycle[0]);
+                bail!("The order of the steps contains a cycle: {}", cycle.join(" -> "));
+            }
+            path.push(previous);
+        }
+    }
+
+    Ok(sorted)
+}
+
+/// Run the pre hook of `step`, unless an entry of the step already ran it, in which case its
+/// result is reused: the pre hook of a step with several entries runs once, before the first
+/// entry that applies.
+fn run_pre_hook(ctx: &ExecutionContext, pre_hooks: &PreHooks, step: Step, key: &str) -> Result<(), String> {
+    let mut pre_hooks = pre_hooks.lock().unwrap();
+    if let Some((_, result)) = pre_hooks.iter().find(|(done, _)| *done == step) {
+        return result.clone();
+    }
+
+    let result = run_hook(ctx, Hook::Pre, step, key, None).map_err(|e| e.to_string());
+    pre_hooks.push((step, result.clone()));
+    result
+}
+
+/// Run the `hook` command of `step`, if there is one. The hooks that run after the step are told
+/// about its result through their environment.
+fn run_hook(
+    ctx: &ExecutionContext,
+    hook: Hook,
+    step: Step,
+    key: &str,
+    outcome: Option<(&StepResult, &[String])>,
+) -> Result<()> {
+    let Some(command) = ctx.config().hook(step, hook) else {
+        return Ok(());
+    };
+    debug!("Running the {} hook of {:?}", hook, key);
+
+    let mut exec = ctx.run_type().execute(shell());
+    exec.arg("-c")
+        .arg(command)
+        .env("TOPGRADE_HOOK", hook.to_string())
+        .env("TOPGRADE_STEP", step.to_string())
+        .env("TOPGRADE_STEP_KEY", key);
+    if let Some((result, errors)) = outcome {
+        exec.env("TOPGRADE_STEP_RESULT", result.name())
+            .env("TOPGRADE_STEP_ERRORS", errors.join("\n"));
+    }
+    exec.status_checked()
+}
+
+/// Run `func` in a tracing span describing the step.
+fn in_span(step: Step, key: &str, func: impl Fn() -> Result<()>) -> Result<()> {
+    let span = tracing::span!(parent: tracing::Span::none(), tracing::Level::TRACE, "step", step = ?step, key = %key);
+    let _guard = span.enter();
+    func()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plan(steps: &[(Step, &'static str)]) -> Vec<Planned<'static>> {
+        steps
+            .iter()
+            .map(|&(step, key)| Planned {
+                step,
+                key: key.into(),
+                func: Box::new(|| Ok(())),
+                before: Vec::new(),
+                detect: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sort_keeps_planned_order() {
+        let plan = plan(&[(Step::Rustup, "rustup"), (Step::Cargo, "cargo"), (Step::Pipx, "pipx")]);
+
+        assert_eq!(sort(&plan, Step::ORDERING).unwrap(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn registry_order_satisfies_its_constraints() {
+        let plan: Vec<_> = crate::registry::entries()
+            .map(|entry| Planned {
+                step: entry.step,
+                key: entry.name.into(),
+                func: Box::new(|| Ok(())),
+                before: entry.before.to_vec(),
+                detect: None,
+            })
+            .collect();
+
+        let sorted = sort(&plan, Step::ORDERING).unwrap();
+        assert!(sorted.iter().enumerate().all(|(i, &j)| i == j));
+    }
+
+    #[test]
+    fn sort_delays_steps_until_their_dependencies_ran() {
+        let mut plan = plan(&[
+            (Step::Shell, "packer.nu"),
+            (Step::Containers, "Containers"),
+            (Step::Helm, "helm"),
+            (Step::CustomCommands, "first"),
+            (Step::CustomCommands, "second"),
+            (Step::Vagrant, "Vagrant boxes"),
+        ]);
+        plan[2].before(&[Step::Shell]);
+
+        let order = [(Step::CustomCommands, Step::Containers)];
+        assert_eq!(sort(&plan, &order).unwrap(), [2, 0, 3, 4, 1, 5]);
+    }
+
+    #[test]
+    fn sort_rejects_cycles() {
+        let mut plan = plan(&[(Step::Rustup, "rustup"), (Step::Cargo, "cargo"), (Step::Pipx, "pipx")]);
+        plan[1].before(&[Step::Pipx]);
+
+        let error = sort(&plan, &[(Step::Rustup, Step::Cargo), (Step::Pipx, Step::Rustup)]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The order of the steps contains a cycle: cargo -> pipx -> rustup -> cargo"
+        );
+    }
 }
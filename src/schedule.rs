@@ -0,0 +1,219 @@
+//! Run Topgrade regularly and unattended, with a systemd user timer or a crontab entry.
+
+#[cfg(unix)]
+use std::env;
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::io::Write;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::process::{Command, Stdio};
+
+#[cfg(windows)]
+use color_eyre::eyre::eyre;
+#[cfg(unix)]
+use color_eyre::eyre::Context;
+use color_eyre::eyre::Result;
+#[cfg(unix)]
+use etcetera::base_strategy::BaseStrategy;
+use rust_i18n::t;
+
+#[cfg(unix)]
+use crate::command::CommandExt;
+use crate::config::Config;
+#[cfg(unix)]
+use crate::utils::which;
+#[cfg(unix)]
+use crate::XDG_DIRS;
+
+/// The name of the systemd units.
+#[cfg(unix)]
+const UNIT: &str = "topgrade";
+
+/// Marks the crontab entry so that it can be replaced or removed.
+#[cfg(unix)]
+const CRON_MARKER: &str = "# Added by `topgrade schedule install`";
+
+#[derive(Clone, Copy, Debug)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+#[cfg(unix)]
+impl Frequency {
+    fn calendar(self) -> &'static str {
+        match self {
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+        }
+    }
+
+    fn cron(self) -> &'static str {
+        match self {
+            Frequency::Daily => "0 4 * * *",
+            Frequency::Weekly => "0 4 * * 1",
+        }
+    }
+}
+
+/// The command line of the scheduled runs, with the current binary and configuration.
+#[cfg(unix)]
+fn command_line(config: &Config) -> Result<String> {
+    let mut args = vec![
+        env::current_exe()?.to_string_lossy().into_owned(),
+        "--unattended".to_string(),
+        "--yes".to_string(),
+    ];
+    if let Some(path) = config.config_path() {
+        let path = fs::canonicalize(path).with_context(|| format!("Failed to find {}", path.display()))?;
+        args.extend(["--config".to_string(), path.to_string_lossy().into_owned()]);
+    }
+
+    Ok(shell_words::join(args))
+}
+
+#[cfg(unix)]
+fn units_dir() -> PathBuf {
+    XDG_DIRS.config_dir().join("systemd").join("user")
+}
+
+#[cfg(unix)]
+fn has_systemd() -> bool {
+    which("systemctl").is_some() && Path::new("/run/systemd/system").is_dir()
+}
+
+#[cfg(unix)]
+fn systemctl(args: &[&str]) -> Result<()> {
+    Command::new("systemctl").arg("--user").args(args).status_checked()
+}
+
+/// Install a systemd user timer, or a crontab entry if `cron` is set or systemd is not available.
+#[cfg(unix)]
+pub fn install(config: &Config, frequency: Frequency, cron: bool) -> Result<()> {
+    let command = command_line(config)?;
+    // Scheduled runs do not get the `PATH` of a login shell.
+    let path = env::var("PATH").unwrap_or_default();
+
+    if !cron && has_systemd() {
+        let service = format!(
+            "[Unit]\nDescription=Upgrade everything with Topgrade\n\n\
+             [Service]\nType=oneshot\nEnvironment={}\nExecStart={command}\n",
+            shell_words::quote(&format!("PATH={path}"))
+        );
+        let timer = format!(
+            "[Unit]\nDescription=Run Topgrade {}\n\n\
+             [Timer]\nOnCalendar={}\nPersistent=true\nRandomizedDelaySec=15m\n\n\
+             [Install]\nWantedBy=timers.target\n",
+            frequency.calendar(),
+            frequency.calendar()
+        );
+
+        fs::create_dir_all(units_dir())?;
+        for (extension, contents) in [("service", service), ("timer", timer)] {
+            let unit = units_dir().join(format!("{UNIT}.{extension}"));
+            fs::write(&unit, contents).with_context(|| format!("Failed to write {}", unit.display()))?;
+            println!("{}", t!("Wrote {path}", path = unit.display()));
+        }
+
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", &format!("{UNIT}.timer")])?;
+        println!("{}", t!("Enabled {unit}", unit = format!("{UNIT}.timer")));
+    } else {
+        let mut crontab = read_crontab()?;
+        crontab.retain(|line| !line.ends_with(CRON_MARKER));
+        let entry = format!("PATH={} {command}", shell_words::quote(&path));
+        // `%` starts the standard input of the command in a crontab
+        crontab.push(format!(
+            "{} {} {CRON_MARKER}",
+            frequency.cron(),
+            entry.replace('%', "\\%")
+        ));
+        write_crontab(&crontab)?;
+        println!("{}", t!("Added a crontab entry running {command}", command = command));
+    }
+
+    Ok(())
+}
+
+/// Remove the timer and crontab entry added by `install`.
+#[cfg(unix)]
+pub fn uninstall() -> Result<()> {
+    let mut removed = false;
+
+    let units = ["timer", "service"].map(|extension| units_dir().join(format!("{UNIT}.{extension}")));
+    if units.iter().any(|unit| unit.exists()) {
+        if has_systemd() {
+            systemctl(&["disable", "--now", &format!("{UNIT}.timer")])?;
+        }
+        for unit in units.iter().filter(|unit| unit.exists()) {
+            fs::remove_file(unit).with_context(|| format!("Failed to remove {}", unit.display()))?;
+            println!("{}", t!("Removed {path}", path = unit.display()));
+        }
+        if has_systemd() {
+            systemctl(&["daemon-reload"])?;
+        }
+        removed = true;
+    }
+
+    if which("crontab").is_some() {
+        let mut crontab = read_crontab()?;
+        let entries = crontab.len();
+        crontab.retain(|line| !line.ends_with(CRON_MARKER));
+        if crontab.len() < entries {
+            write_crontab(&crontab)?;
+            println!("{}", t!("Removed the crontab entry"));
+            removed = true;
+        }
+    }
+
+    if !removed {
+        println!("{}", t!("Topgrade is not scheduled"));
+    }
+
+    Ok(())
+}
+
+/// The lines of the current user's crontab.
+#[cfg(unix)]
+fn read_crontab() -> Result<Vec<String>> {
+    // `crontab -l` fails if the user has no crontab yet.
+    let output = Command::new("crontab").arg("-l").output_checked_with_utf8(|_| Ok(()))?;
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+
+    Ok(output.stdout.lines().map(str::to_string).collect())
+}
+
+#[cfg(unix)]
+fn write_crontab(lines: &[String]) -> Result<()> {
+    let mut child = Command::new("crontab").arg("-").stdin(Stdio::piped()).spawn_checked()?;
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(contents.as_bytes())
+        .context("Failed to write the crontab")?;
+
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(crate::error::TopgradeError::ProcessFailed("crontab".to_string(), status).into())
+    }
+}
+
+#[cfg(windows)]
+pub fn install(_: &Config, _: Frequency, _: bool) -> Result<()> {
+    Err(eyre!(t!("Scheduling runs is only supported on Unix")))
+}
+
+#[cfg(windows)]
+pub fn uninstall() -> Result<()> {
+    Err(eyre!(t!("Scheduling runs is only supported on Unix")))
+}
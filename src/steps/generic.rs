@@ -17,7 +17,8 @@ use tracing::{debug, error, warn};
 use crate::command::{CommandExt, Utf8Output};
 use crate::execution_context::ExecutionContext;
 use crate::executor::ExecutorOutput;
-use crate::terminal::{print_separator, shell};
+use crate::report::{parse_versions, PackageChange, Versions};
+use crate::terminal::{print_line, print_separator, shell};
 use crate::utils::{
     check_is_python_2_or_shim, get_require_sudo_string, require, require_one, require_option, which, PathExt,
 };
@@ -40,6 +41,57 @@ pub fn is_wsl() -> Result<bool> {
     Ok(false)
 }
 
+fn find_cargo_update(cargo_dir: &Path) -> Option<PathBuf> {
+    require("cargo-install-update")
+        .ok()
+        .or_else(|| cargo_dir.join("bin/cargo-install-update").if_exists())
+}
+
+pub fn list_cargo(_: &ExecutionContext) -> Result<Versions> {
+    let output = Command::new(require("cargo")?)
+        .args(["install", "--list"])
+        .output_checked_utf8()?;
+
+    // Packages look like `ripgrep v14.1.0:`, followed by their binaries on indented lines
+    Ok(output
+        .stdout
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let version = fields.next()?.trim_end_matches(':');
+            Some((name.to_string(), version.trim_start_matches('v').to_string()))
+        })
+        .collect())
+}
+
+pub fn check_cargo(_: &ExecutionContext) -> Result<Vec<PackageChange>> {
+    let cargo_dir = env::var_os("CARGO_HOME").map_or_else(|| HOME_DIR.join(".cargo"), PathBuf::from);
+    let cargo_update = require_option(
+        find_cargo_update(&cargo_dir),
+        String::from("cargo-update isn't installed so Topgrade can't check cargo packages"),
+    )?;
+
+    let output = Command::new(cargo_update)
+        .args(["install-update", "--list"])
+        .output_checked_utf8()?;
+
+    // Rows look like `cargo-update  v13.0.0  v13.4.0  Yes`
+    Ok(output
+        .stdout
+        .lines()
+        .filter_map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [name, installed, latest, "Yes"] => Some(PackageChange::new(
+                name,
+                Some(installed.to_string()),
+                Some(latest.to_string()),
+            )),
+            _ => None,
+        })
+        .collect())
+}
+
 pub fn run_cargo_update(ctx: &ExecutionContext) -> Result<()> {
     let cargo_dir = env::var_os("CARGO_HOME")
         .map_or_else(|| HOME_DIR.join(".cargo"), PathBuf::from)
@@ -58,11 +110,7 @@ pub fn run_cargo_update(ctx: &ExecutionContext) -> Result<()> {
     }
 
     print_separator("Cargo");
-    let cargo_update = require("cargo-install-update")
-        .ok()
-        .or_else(|| cargo_dir.join("bin/cargo-install-update").if_exists());
-
-    let Some(cargo_update) = cargo_update else {
+    let Some(cargo_update) = find_cargo_update(&cargo_dir) else {
         let message = String::from("cargo-update isn't installed so Topgrade can't upgrade cargo packages.\nInstall cargo-update by running `cargo install cargo-update`");
         print_warning(&message);
         return Err(SkipStep(message).into());
@@ -265,8 +313,8 @@ pub fn run_aqua(ctx: &ExecutionContext) -> Result<()> {
 
     print_separator("Aqua");
     if ctx.run_type().dry() {
-        println!("{}", t!("Updating aqua ..."));
-        println!("{}", t!("Updating aqua installed cli tools ..."));
+        print_line(t!("Updating aqua ..."));
+        print_line(t!("Updating aqua installed cli tools ..."));
         Ok(())
     } else {
         ctx.run_type().execute(&aqua).arg("update-aqua").status_checked()?;
@@ -542,6 +590,14 @@ pub fn run_pipx_update(ctx: &ExecutionContext) -> Result<()> {
     ctx.run_type().execute(pipx).args(command_args).status_checked()
 }
 
+pub fn list_pipx(_: &ExecutionContext) -> Result<Versions> {
+    let output = Command::new(require("pipx")?)
+        .args(["list", "--short"])
+        .output_checked_utf8()?;
+
+    Ok(parse_versions(&output.stdout))
+}
+
 pub fn run_pipxu_update(ctx: &ExecutionContext) -> Result<()> {
     let pipxu = require("pipxu")?;
     print_separator("pipxu");
@@ -1538,11 +1594,10 @@ pub fn run_jetbrains_toolbox(_ctx: &ExecutionContext) -> Result<()> {
         }
         Err(e) => {
             // Unexpected error
-            println!(
-                "{}",
-                t!("jetbrains-toolbox-updater encountered an unexpected error during finding:")
-            );
-            println!("{e:?}");
+            print_line(t!(
+                "jetbrains-toolbox-updater encountered an unexpected error during finding:"
+            ));
+            print_line(format!("{e:?}"));
             Err(StepFailed.into())
         }
         Ok(installation) => {
@@ -1551,11 +1606,10 @@ pub fn run_jetbrains_toolbox(_ctx: &ExecutionContext) -> Result<()> {
             match update_jetbrains_toolbox(installation) {
                 Err(e) => {
                     // Unexpected error
-                    println!(
-                        "{}",
-                        t!("jetbrains-toolbox-updater encountered an unexpected error during updating:")
-                    );
-                    println!("{e:?}");
+                    print_line(t!(
+                        "jetbrains-toolbox-updater encountered an unexpected error during updating:"
+                    ));
+                    print_line(format!("{e:?}"));
                     Err(StepFailed.into())
                 }
                 Ok(()) => Ok(()),
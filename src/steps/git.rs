@@ -16,7 +16,7 @@ use crate::command::CommandExt;
 use crate::config::Step;
 use crate::execution_context::ExecutionContext;
 use crate::steps::emacs::Emacs;
-use crate::terminal::print_separator;
+use crate::terminal::{print_line, print_separator};
 use crate::utils::{require, PathExt};
 use crate::{error::SkipStep, terminal::print_warning, HOME_DIR};
 use etcetera::base_strategy::BaseStrategy;
@@ -302,7 +302,11 @@ impl RepoStep {
         let before_revision = get_head_revision(&self.git, &repo);
 
         if ctx.config().verbose() {
-            println!("{} {}", style(t!("Pulling")).cyan().bold(), repo.as_ref().display());
+            print_line(format!(
+                "{} {}",
+                style(t!("Pulling")).cyan().bold(),
+                repo.as_ref().display()
+            ));
         }
 
         let mut command = AsyncCommand::new(&self.git);
@@ -328,18 +332,22 @@ impl RepoStep {
             .wrap_err_with(|| format!("Failed to pull {}", repo.as_ref().display()));
 
         if result.is_err() {
-            println!(
+            print_line(format!(
                 "{} {} {}",
                 style(t!("Failed")).red().bold(),
                 t!("pulling"),
                 repo.as_ref().display()
-            );
+            ));
         } else {
             let after_revision = get_head_revision(&self.git, repo.as_ref());
 
             match (&before_revision, &after_revision) {
                 (Some(before), Some(after)) if before != after => {
-                    println!("{} {}", style(t!("Changed")).yellow().bold(), repo.as_ref().display());
+                    print_line(format!(
+                        "{} {}",
+                        style(t!("Changed")).yellow().bold(),
+                        repo.as_ref().display()
+                    ));
 
                     Command::new(&self.git)
                         .stdin(Stdio::null())
@@ -352,11 +360,15 @@ impl RepoStep {
                             &format!("{before}..{after}"),
                         ])
                         .status_checked()?;
-                    println!();
+                    print_line("");
                 }
                 _ => {
                     if ctx.config().verbose() {
-                        println!("{} {}", style(t!("Up-to-date")).green().bold(), repo.as_ref().display());
+                        print_line(format!(
+                            "{} {}",
+                            style(t!("Up-to-date")).green().bold(),
+                            repo.as_ref().display()
+                        ));
                     }
                 }
             }
@@ -374,17 +386,17 @@ impl RepoStep {
         if ctx.run_type().dry() {
             self.repos
                 .iter()
-                .for_each(|repo| println!("{}", t!("Would pull {repo}", repo = repo.display())));
+                .for_each(|repo| print_line(t!("Would pull {repo}", repo = repo.display())));
 
             return Ok(());
         }
 
         if !ctx.config().verbose() {
-            println!(
+            print_line(format!(
                 "\n{} {}\n",
                 style(t!("Only")).green().bold(),
                 t!("updated repositories will be shown...")
-            );
+            ));
         }
 
         let futures_iterator = self
@@ -392,12 +404,12 @@ impl RepoStep {
             .iter()
             .filter(|repo| match self.has_remotes(repo) {
                 Some(false) => {
-                    println!(
+                    print_line(format!(
                         "{} {} {}",
                         style(t!("Skipping")).yellow().bold(),
                         repo.display(),
                         t!("because it has no remotes")
-                    );
+                    ));
                     false
                 }
                 _ => true, // repo has remotes or command to check for remotes has failed. proceed to pull anyway.
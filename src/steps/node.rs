@@ -14,6 +14,7 @@ use semver::Version;
 use tracing::debug;
 
 use crate::command::CommandExt;
+use crate::report::{PackageChange, Versions};
 use crate::terminal::{print_info, print_separator};
 use crate::utils::{require, PathExt};
 use crate::{error::SkipStep, execution_context::ExecutionContext};
@@ -314,6 +315,39 @@ pub fn run_npm_upgrade(ctx: &ExecutionContext) -> Result<()> {
     }
 }
 
+pub fn list_npm(_: &ExecutionContext) -> Result<Versions> {
+    let output = Command::new(require("npm")?)
+        .args(["list", "--global", "--depth=0", "--json"])
+        .output_checked_utf8()?;
+
+    let listing: serde_json::Value = serde_json::from_str(&output.stdout)?;
+    Ok(listing["dependencies"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, package)| Some((name.clone(), package["version"].as_str()?.to_string())))
+        .collect())
+}
+
+pub fn check_npm(_: &ExecutionContext) -> Result<Vec<PackageChange>> {
+    // Exits with 1 if some packages are outdated
+    let output = Command::new(require("npm")?)
+        .args(["outdated", "--global", "--json"])
+        .output_checked_with_utf8(|_| Ok(()))?;
+
+    if output.stdout.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let outdated: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&output.stdout)?;
+    let version = |package: &serde_json::Value, field: &str| package[field].as_str().map(str::to_string);
+
+    Ok(outdated
+        .iter()
+        .map(|(name, package)| PackageChange::new(name, version(package, "current"), version(package, "latest")))
+        .collect())
+}
+
 pub fn run_pnpm_upgrade(ctx: &ExecutionContext) -> Result<()> {
     let pnpm = require("pnpm").map(|b| NPM::new(b, NPMVariant::Pnpm))?;
 
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -9,14 +11,18 @@ use tracing::{debug, warn};
 use crate::command::CommandExt;
 use crate::error::{SkipStep, TopgradeError};
 use crate::execution_context::ExecutionContext;
+use crate::report::{parse_versions, PackageChange, Versions};
 use crate::steps::generic::is_wsl;
-use crate::steps::os::archlinux;
+use crate::steps::os::{archlinux, snapshot};
 use crate::terminal::{print_separator, prompt_yesno};
 use crate::utils::{get_require_sudo_string, require, require_option, which, PathExt};
 use crate::{Step, HOME_DIR};
 
 static OS_RELEASE_PATH: &str = "/etc/os-release";
 
+/// Created by Debian based distributions when an upgraded package needs a reboot.
+static REBOOT_REQUIRED_PATH: &str = "/var/run/reboot-required";
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Distribution {
@@ -134,6 +140,7 @@ impl Distribution {
 
     pub fn upgrade(self, ctx: &ExecutionContext) -> Result<()> {
         print_separator(t!("System update"));
+        let pre = snapshot::before_upgrade(ctx)?;
 
         match self {
             Distribution::Alpine => upgrade_alpine_linux(ctx),
@@ -159,20 +166,128 @@ impl Distribution {
             Distribution::PCLinuxOS => upgrade_pclinuxos(ctx),
             Distribution::Nobara => upgrade_nobara(ctx),
             Distribution::NILRT => upgrade_nilrt(ctx),
+        }?;
+
+        snapshot::after_upgrade(ctx, pre.as_ref())
+    }
+
+    /// List the packages the system package manager can upgrade, without upgrading anything.
+    pub fn check_updates(self) -> Result<Vec<PackageChange>> {
+        match self {
+            Distribution::Debian => check_debian(),
+            Distribution::CentOS | Distribution::Fedora => check_redhat(),
+            _ => Err(SkipStep(t!("Checking for updates is not supported").to_string()).into()),
         }
     }
 
+    /// The versions of the packages installed by the system package manager.
+    pub fn installed_packages(self) -> Result<Versions> {
+        let mut command = match self {
+            Distribution::Arch => {
+                let mut command = Command::new("pacman");
+                command.arg("-Q");
+                command
+            }
+            Distribution::CentOS | Distribution::Fedora => {
+                let mut command = Command::new("rpm");
+                command.args(["-qa", "--queryformat", "%{NAME} %{VERSION}-%{RELEASE}\\n"]);
+                command
+            }
+            Distribution::Debian => {
+                let mut command = Command::new("dpkg-query");
+                command.args(["--show", "--showformat", "${Package} ${Version}\\n"]);
+                command
+            }
+            _ => return Err(SkipStep(format!("Cannot list the installed packages of {self:?}")).into()),
+        };
+
+        Ok(parse_versions(&command.output_checked_utf8()?.stdout))
+    }
+
     pub fn show_summary(self) {
         if let Distribution::Arch = self {
             archlinux::show_pacnew();
         }
     }
 
+    /// Why the machine has to be rebooted to finish the upgrade, if it has to.
+    pub fn reboot_required(self) -> Option<String> {
+        if Path::new(REBOOT_REQUIRED_PATH).exists() {
+            return Some(t!("{path} exists", path = REBOOT_REQUIRED_PATH).to_string());
+        }
+
+        match self {
+            Distribution::CentOS | Distribution::Fedora => exits_with("needs-restarting", &["-r"], 1).then(|| {
+                t!(
+                    "{command} reports that core packages were updated",
+                    command = "needs-restarting -r"
+                )
+            }),
+            Distribution::Suse | Distribution::OpenSuseTumbleweed => exits_with("zypper", &["needs-rebooting"], 102)
+                .then(|| {
+                    t!(
+                        "{command} reports that core packages were updated",
+                        command = "zypper needs-rebooting"
+                    )
+                }),
+            Distribution::Arch => running_kernel_removed(),
+            Distribution::FedoraImmutable => deployment_pending(),
+            Distribution::NixOS => nixos_boot_changed(),
+            _ => None,
+        }
+        .map(|reason| reason.to_string())
+    }
+
     pub fn redhat_based(self) -> bool {
         matches!(self, Distribution::CentOS | Distribution::Fedora)
     }
 }
 
+/// Whether `program` is installed and exits with `code`, which it uses to report something else
+/// than a failure.
+fn exits_with(program: &str, args: &[&str], code: i32) -> bool {
+    which(program).is_some_and(|program| {
+        Command::new(program)
+            .args(args)
+            .output_checked_with(|output| {
+                if output.status.code() == Some(code) {
+                    Ok(())
+                } else {
+                    Err(())
+                }
+            })
+            .is_ok()
+    })
+}
+
+/// Upgrading the kernel removes the modules of the running one.
+fn running_kernel_removed() -> Option<Cow<'static, str>> {
+    let release = Command::new("uname").arg("-r").output_checked_utf8().ok()?.stdout;
+    let release = release.trim();
+    (!Path::new("/usr/lib/modules").join(release).exists())
+        .then(|| t!("The running kernel {version} is no longer installed", version = release))
+}
+
+/// rpm-ostree lists the deployment booted next first.
+fn deployment_pending() -> Option<Cow<'static, str>> {
+    let output = Command::new(which("rpm-ostree")?)
+        .args(["status", "--json"])
+        .output_checked_utf8()
+        .ok()?;
+    let status: serde_json::Value = serde_json::from_str(&output.stdout).ok()?;
+    let booted = status["deployments"][0]["booted"].as_bool()?;
+    (!booted).then(|| t!("A new deployment is pending"))
+}
+
+/// The current generation boots another kernel or initrd than the booted one.
+fn nixos_boot_changed() -> Option<Cow<'static, str>> {
+    let changed = ["kernel", "initrd", "kernel-modules"].iter().any(|link| {
+        fs::canonicalize(Path::new("/run/booted-system").join(link)).ok()
+            != fs::canonicalize(Path::new("/nix/var/nix/profiles/system").join(link)).ok()
+    });
+    changed.then(|| t!("The current generation boots another kernel"))
+}
+
 fn update_bedrock(ctx: &ExecutionContext) -> Result<()> {
     let sudo = require_option(ctx.sudo().as_ref(), get_require_sudo_string())?;
 
@@ -578,6 +693,61 @@ fn upgrade_debian(ctx: &ExecutionContext) -> Result<()> {
     Ok(())
 }
 
+/// Uses the package lists of the last `apt update`, refreshing them requires root.
+fn check_debian() -> Result<Vec<PackageChange>> {
+    let output = Command::new(require("apt")?)
+        .args(["list", "--upgradable"])
+        .output_checked_utf8()?;
+
+    Ok(parse_apt_upgradable(&output.stdout))
+}
+
+/// Parse the output of `apt list --upgradable`, whose lines look like
+/// `curl/jammy-updates 7.81.0-1ubuntu1.16 amd64 [upgradable from: 7.81.0-1ubuntu1.15]`.
+fn parse_apt_upgradable(output: &str) -> Vec<PackageChange> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (name, _) = fields.next()?.split_once('/')?;
+            let to = fields.next()?;
+            let from = line
+                .split_once("upgradable from: ")
+                .map(|(_, from)| from.trim_end_matches(']').trim().to_string());
+            Some(PackageChange::new(name, from, Some(to.to_string())))
+        })
+        .collect()
+}
+
+fn check_redhat() -> Result<Vec<PackageChange>> {
+    let output = Command::new(which("dnf").unwrap_or_else(|| PathBuf::from("yum")))
+        .args(["check-update", "--quiet"])
+        // Exits with 100 if updates are available
+        .output_checked_with_utf8(|output| match output.status.code() {
+            Some(0 | 100) => Ok(()),
+            _ => Err(()),
+        })?;
+
+    Ok(parse_dnf_check_update(&output.stdout))
+}
+
+/// Parse the output of `dnf check-update`, whose lines look like
+/// `curl.x86_64    8.2.1-5.fc39    updates`.
+fn parse_dnf_check_update(output: &str) -> Vec<PackageChange> {
+    output
+        .lines()
+        // Packages replaced by others are listed after the updates
+        .take_while(|line| !line.starts_with("Obsoleting"))
+        .filter_map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [package, version, _repository] => {
+                let name = package.rsplit_once('.').map_or(package, |(name, _arch)| name);
+                Some(PackageChange::new(name, None, Some(version.to_string())))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
 pub fn run_deb_get(ctx: &ExecutionContext) -> Result<()> {
     let deb_get = require("deb-get")?;
 
@@ -1132,6 +1302,31 @@ pub fn run_cinnamon_spices_updater(ctx: &ExecutionContext) -> Result<()> {
         .status_checked()
 }
 
+pub fn list_flatpak(_: &ExecutionContext) -> Result<Versions> {
+    let output = Command::new(require("flatpak")?)
+        .args(["list", "--app", "--columns=application,version"])
+        .output_checked_utf8()?;
+
+    Ok(parse_versions(&output.stdout))
+}
+
+pub fn check_flatpak(_: &ExecutionContext) -> Result<Vec<PackageChange>> {
+    let output = Command::new(require("flatpak")?)
+        .args(["remote-ls", "--updates", "--columns=application,version"])
+        .output_checked_utf8()?;
+
+    Ok(output
+        .stdout
+        .lines()
+        .filter_map(|line| {
+            let mut columns = line.split('\t').map(str::trim);
+            let application = columns.next().filter(|a| !a.is_empty() && *a != "Application ID")?;
+            let version = columns.next().filter(|v| !v.is_empty()).map(str::to_string);
+            Some(PackageChange::new(application, None, version))
+        })
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1325,4 +1520,44 @@ mod tests {
     fn test_cachyos() {
         test_template(include_str!("os_release/cachyos"), Distribution::Arch);
     }
+
+    #[test]
+    fn test_parse_apt_upgradable() {
+        let output = "Listing... Done\n\
+            curl/jammy-updates 7.81.0-1ubuntu1.16 amd64 [upgradable from: 7.81.0-1ubuntu1.15]\n\
+            libcurl4/jammy-updates,jammy-security 7.81.0-1ubuntu1.16 amd64 [upgradable from: 7.81.0-1ubuntu1.15]\n";
+
+        assert_eq!(
+            parse_apt_upgradable(output),
+            vec![
+                PackageChange::new(
+                    "curl",
+                    Some("7.81.0-1ubuntu1.15".to_string()),
+                    Some("7.81.0-1ubuntu1.16".to_string())
+                ),
+                PackageChange::new(
+                    "libcurl4",
+                    Some("7.81.0-1ubuntu1.15".to_string()),
+                    Some("7.81.0-1ubuntu1.16".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_dnf_check_update() {
+        let output = "\n\
+            curl.x86_64                 8.2.1-5.fc39          updates\n\
+            python3-libs.x86_64         3.12.4-1.fc39         updates\n\
+            Obsoleting Packages\n\
+            grub2-tools.x86_64          1:2.06-121.fc39       updates\n";
+
+        assert_eq!(
+            parse_dnf_check_update(output),
+            vec![
+                PackageChange::new("curl", None, Some("8.2.1-5.fc39".to_string())),
+                PackageChange::new("python3-libs", None, Some("3.12.4-1.fc39".to_string())),
+            ]
+        );
+    }
 }
@@ -0,0 +1,115 @@
+//! Filesystem snapshots taken around the system upgrade, from `[linux] snapshot`, so that a
+//! broken upgrade can be rolled back. The snapshots end up in the report of the step and are
+//! listed by `topgrade rollback --list`.
+
+use std::process::Command;
+use std::sync::{LazyLock, Mutex};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use regex::Regex;
+use rust_i18n::t;
+
+use crate::command::CommandExt;
+use crate::config::SnapshotTool;
+use crate::error::DryRun;
+use crate::execution_context::ExecutionContext;
+use crate::executor::Executor;
+use crate::report::{record_snapshot, Snapshot, SnapshotPhase};
+use crate::terminal::print_info;
+use crate::utils::which;
+
+/// Timeshift prints the name of the snapshot it created as `Tagged snapshot '<name>': ondemand`.
+static TIMESHIFT_SNAPSHOT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"snapshot '([^']+)'").unwrap());
+
+/// The snapshot taken before the system upgrade, which the retries of the upgrade reuse.
+static BEFORE_UPGRADE: Mutex<Option<Snapshot>> = Mutex::new(None);
+
+/// Take a snapshot before the system upgrade, if one is configured. A single snapshot is taken
+/// per run, however many times the upgrade is retried.
+pub fn before_upgrade(ctx: &ExecutionContext) -> Result<Option<Snapshot>> {
+    let Some(tool) = ctx.config().snapshot_tool() else {
+        return Ok(None);
+    };
+
+    let mut before = BEFORE_UPGRADE.lock().unwrap();
+    if before.is_none() {
+        *before =
+            take(ctx, tool, SnapshotPhase::Pre, None).wrap_err(t!("Failed to take a snapshot before the upgrade"))?;
+    }
+    Ok(before.clone())
+}
+
+/// Take a snapshot after the system upgrade, if one is configured with `snapshot_after`. `pre` is
+/// the snapshot taken before the upgrade, which snapper pairs it with.
+pub fn after_upgrade(ctx: &ExecutionContext, pre: Option<&Snapshot>) -> Result<()> {
+    let Some(tool) = ctx.config().snapshot_tool().filter(|_| ctx.config().snapshot_after()) else {
+        return Ok(());
+    };
+
+    take(ctx, tool, SnapshotPhase::Post, pre).wrap_err(t!("Failed to take a snapshot after the upgrade"))?;
+    Ok(())
+}
+
+/// Take a snapshot labelled with the ID of the run and record it. Nothing is taken on a dry run.
+fn take(
+    ctx: &ExecutionContext,
+    tool: SnapshotTool,
+    phase: SnapshotPhase,
+    pre: Option<&Snapshot>,
+) -> Result<Option<Snapshot>> {
+    let program = which(tool.to_string()).ok_or_else(|| eyre!(t!("{tool} is not installed", tool = tool)))?;
+    let label = format!("topgrade-{}-{phase}", ctx.run_id());
+    let description = format!("topgrade {} {phase}", ctx.run_id());
+    let mut exec = ctx.execute_elevated(&program, false)?;
+
+    let id = match tool {
+        SnapshotTool::Snapper => {
+            exec.args(["create", "--cleanup-algorithm", "number", "--print-number"])
+                .args(["--description", &description]);
+            match (phase, pre) {
+                (SnapshotPhase::Pre, _) => exec.args(["--type", "pre"]),
+                (SnapshotPhase::Post, Some(pre)) => exec.args(["--type", "post", "--pre-number", &pre.id]),
+                (SnapshotPhase::Post, None) => exec.args(["--type", "single"]),
+            };
+            stdout(&mut exec)?.map(|stdout| stdout.trim().to_string())
+        }
+        SnapshotTool::Btrfs => {
+            let path = ctx.config().btrfs_snapshot_dir().join(&label);
+            exec.args(["subvolume", "snapshot", "-r", "/"]).arg(&path);
+            stdout(&mut exec)?.map(|_| path.display().to_string())
+        }
+        SnapshotTool::Timeshift => {
+            exec.args(["--create", "--scripted", "--comments", &description]);
+            stdout(&mut exec)?.map(|stdout| {
+                TIMESHIFT_SNAPSHOT
+                    .captures(&stdout)
+                    .map_or_else(|| description.clone(), |captures| captures[1].to_string())
+            })
+        }
+        SnapshotTool::Zfs => {
+            let dataset = Command::new("findmnt")
+                .args(["--noheadings", "--output", "SOURCE", "/"])
+                .output_checked_utf8()?
+                .stdout;
+            let name = format!("{}@{label}", dataset.trim());
+            exec.args(["snapshot", &name]);
+            stdout(&mut exec)?.map(|_| name)
+        }
+    };
+
+    Ok(id.map(|id| {
+        let snapshot = Snapshot { tool, phase, id };
+        print_info(t!("Created the snapshot {snapshot}", snapshot = snapshot));
+        record_snapshot(snapshot.clone());
+        snapshot
+    }))
+}
+
+/// Run `exec`, returning its standard output, or `None` on a dry run.
+fn stdout(exec: &mut Executor) -> Result<Option<String>> {
+    match exec.output_checked_utf8() {
+        Ok(output) => Ok(Some(output.stdout)),
+        Err(e) if e.is::<DryRun>() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -45,7 +45,12 @@ pub fn ssh_step(ctx: &ExecutionContext, hostname: &str) -> Result<()> {
         }
 
         let env = format!("TOPGRADE_PREFIX={hostname}");
-        args.extend(["env", &env, "$SHELL", "-lc", topgrade]);
+        let topgrade = if ctx.config().unattended() {
+            format!("{topgrade} --unattended")
+        } else {
+            topgrade.to_string()
+        };
+        args.extend(["env", &env, "$SHELL", "-lc", &topgrade]);
 
         print_separator(format!("Remote ({hostname})"));
         println!("{}", t!("Connecting to {hostname}...", hostname = hostname));
@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::{fmt::Display, rc::Rc, str::FromStr};
+use std::{fmt::Display, str::FromStr, sync::Arc};
 
 use color_eyre::eyre::Result;
 use regex::Regex;
@@ -30,7 +30,7 @@ impl BoxStatus {
 
 #[derive(Debug)]
 pub struct VagrantBox {
-    path: Rc<Path>,
+    path: Arc<Path>,
     name: String,
     initial_status: BoxStatus,
 }
@@ -57,7 +57,7 @@ struct Vagrant {
 
 impl Vagrant {
     fn get_boxes(&self, directory: &str) -> Result<Vec<VagrantBox>> {
-        let path: Rc<Path> = Path::new(directory).into();
+        let path: Arc<Path> = Path::new(directory).into();
 
         let output = Command::new(&self.path)
             .arg("status")
@@ -4,7 +4,7 @@ use std::path::PathBuf;
 
 use color_eyre::eyre::Context;
 use color_eyre::eyre::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use strum::AsRefStr;
 
 use crate::command::CommandExt;
@@ -131,7 +131,7 @@ impl Sudo {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, AsRefStr)]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, AsRefStr)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "lowercase")]
 pub enum SudoKind {
@@ -1,5 +1,7 @@
+use std::cell::RefCell;
 use std::cmp::{max, min};
 use std::env;
+use std::fmt;
 use std::io::{self, Write};
 use std::process::Command;
 use std::sync::{LazyLock, Mutex};
@@ -16,10 +18,17 @@ use tracing::{debug, error};
 use which_crate::which;
 
 use crate::command::CommandExt;
+use crate::log_file;
 use crate::report::StepResult;
+use crate::utils::format_duration;
 
 static TERMINAL: LazyLock<Mutex<Terminal>> = LazyLock::new(|| Mutex::new(Terminal::new()));
 
+thread_local! {
+    /// Output of the step running on this thread, if it is being buffered rather than printed.
+    static CAPTURED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
 #[cfg(unix)]
 pub fn shell() -> String {
     env::var("SHELL").unwrap_or_else(|_| "sh".to_string())
@@ -41,6 +50,7 @@ struct Terminal {
     set_title: bool,
     display_time: bool,
     desktop_notification: bool,
+    unattended: bool,
 }
 
 impl Terminal {
@@ -53,6 +63,7 @@ impl Terminal {
             set_title: true,
             display_time: true,
             desktop_notification: false,
+            unattended: false,
         }
     }
 
@@ -68,6 +79,10 @@ impl Terminal {
         self.display_time = display_time;
     }
 
+    fn set_unattended(&mut self, unattended: bool) {
+        self.unattended = unattended;
+    }
+
     fn notify_desktop<P: AsRef<str>>(&self, message: P, timeout: Option<Duration>) {
         debug!("Desktop notification: {}", message.as_ref());
         let mut notification = Notification::new();
@@ -82,8 +97,19 @@ impl Terminal {
         notification.show().ok();
     }
 
+    /// Write to the terminal, or to the output buffer if the current step's output is captured.
+    fn write_fmt(&mut self, args: fmt::Arguments) -> io::Result<()> {
+        let output = args.to_string();
+        if write_captured(&output) {
+            Ok(())
+        } else {
+            log_file::write(&output);
+            self.term.write_str(&output)
+        }
+    }
+
     fn print_separator<P: AsRef<str>>(&mut self, message: P) {
-        if self.set_title {
+        if self.set_title && !is_capturing() {
             self.term
                 .set_title(format!("{}Topgrade - {}", self.prefix, message.as_ref()));
         }
@@ -108,27 +134,26 @@ impl Terminal {
 
         match self.width {
             Some(width) => {
-                self.term
-                    .write_fmt(format_args!(
-                        "{}\n",
-                        style(format_args!(
-                            "\n── {} {:─^border$}",
-                            message,
-                            "",
-                            border = max(
-                                2,
-                                min(80, width as usize)
-                                    .checked_sub(4)
-                                    .and_then(|e| e.checked_sub(message.len()))
-                                    .unwrap_or(0)
-                            )
-                        ))
-                        .bold()
+                self.write_fmt(format_args!(
+                    "{}\n",
+                    style(format_args!(
+                        "\n── {} {:─^border$}",
+                        message,
+                        "",
+                        border = max(
+                            2,
+                            min(80, width as usize)
+                                .checked_sub(4)
+                                .and_then(|e| e.checked_sub(message.len()))
+                                .unwrap_or(0)
+                        )
                     ))
-                    .ok();
+                    .bold()
+                ))
+                .ok();
             }
             None => {
-                self.term.write_fmt(format_args!("―― {message} ――\n")).ok();
+                self.write_fmt(format_args!("―― {message} ――\n")).ok();
             }
         }
     }
@@ -137,50 +162,59 @@ impl Terminal {
     fn print_error<P: AsRef<str>, Q: AsRef<str>>(&mut self, key: Q, message: P) {
         let key = key.as_ref();
         let message = message.as_ref();
-        self.term
-            .write_fmt(format_args!(
-                "{} {}",
-                style(format!("{}", t!("{key} failed:", key = key))).red().bold(),
-                message
-            ))
-            .ok();
+        self.write_fmt(format_args!(
+            "{} {}",
+            style(format!("{}", t!("{key} failed:", key = key))).red().bold(),
+            message
+        ))
+        .ok();
     }
 
     #[allow(dead_code)]
     fn print_warning<P: AsRef<str>>(&mut self, message: P) {
         let message = message.as_ref();
-        self.term
-            .write_fmt(format_args!("{}\n", style(message).yellow().bold()))
+        self.write_fmt(format_args!("{}\n", style(message).yellow().bold()))
             .ok();
     }
 
     #[allow(dead_code)]
     fn print_info<P: AsRef<str>>(&mut self, message: P) {
         let message = message.as_ref();
-        self.term
-            .write_fmt(format_args!("{}\n", style(message).blue().bold()))
-            .ok();
+        self.write_fmt(format_args!("{}\n", style(message).blue().bold())).ok();
     }
 
-    fn print_result<P: AsRef<str>>(&mut self, key: P, result: &StepResult) {
+    fn print_line<P: AsRef<str>>(&mut self, message: P) {
+        self.write_fmt(format_args!("{}\n", message.as_ref())).ok();
+    }
+
+    fn print_result<P: AsRef<str>>(&mut self, key: P, result: &StepResult, duration: Option<Duration>) {
         let key = key.as_ref();
 
-        self.term
-            .write_fmt(format_args!(
-                "{}: {}\n",
-                key,
-                match result {
-                    StepResult::Success => format!("{}", style(t!("OK")).bold().green()),
-                    StepResult::Failure => format!("{}", style(t!("FAILED")).bold().red()),
-                    StepResult::Ignored => format!("{}", style(t!("IGNORED")).bold().yellow()),
-                    StepResult::Skipped(reason) => format!("{}: {}", style(t!("SKIPPED")).bold().blue(), reason),
-                }
-            ))
-            .ok();
+        self.write_fmt(format_args!(
+            "{}: {}{}\n",
+            key,
+            match result {
+                StepResult::Success => format!("{}", style(t!("OK")).bold().green()),
+                StepResult::Failure => format!("{}", style(t!("FAILED")).bold().red()),
+                StepResult::TimedOut => format!("{}", style(t!("TIMED OUT")).bold().red()),
+                StepResult::Ignored => format!("{}", style(t!("IGNORED")).bold().yellow()),
+                StepResult::Skipped(reason) => format!("{}: {}", style(t!("SKIPPED")).bold().blue(), reason),
+            },
+            duration.map_or_else(String::new, |d| format!(" {}", style(format_duration(d)).dim()))
+        ))
+        .ok();
     }
 
     #[allow(dead_code)]
     fn prompt_yesno(&mut self, question: &str) -> Result<bool, io::Error> {
+        if self.unattended {
+            self.print_warning(t!(
+                "Running unattended, answering no to: {question}",
+                question = question
+            ));
+            return Ok(false);
+        }
+
         self.term
             .write_fmt(format_args!(
                 "{}",
@@ -198,6 +232,15 @@ impl Terminal {
     }
     #[allow(unused_variables)]
     fn should_retry(&mut self, interrupted: bool, step_name: &str) -> eyre::Result<bool> {
+        if self.unattended {
+            // The error printed before does not end with a new line
+            self.print_warning(format!(
+                "\n{}",
+                t!("Running unattended, not retrying {step_name}", step_name = step_name)
+            ));
+            return Ok(false);
+        }
+
         if self.width.is_none() {
             return Ok(false);
         }
@@ -281,8 +324,13 @@ pub fn print_info<P: AsRef<str>>(message: P) {
     TERMINAL.lock().unwrap().print_info(message);
 }
 
-pub fn print_result<P: AsRef<str>>(key: P, result: &StepResult) {
-    TERMINAL.lock().unwrap().print_result(key, result);
+/// Print a line of plain text, buffering it if the current step's output is captured.
+pub fn print_line<P: AsRef<str>>(message: P) {
+    TERMINAL.lock().unwrap().print_line(message);
+}
+
+pub fn print_result<P: AsRef<str>>(key: P, result: &StepResult, duration: Option<Duration>) {
+    TERMINAL.lock().unwrap().print_result(key, result, duration);
 }
 
 /// Tells whether the terminal is dumb.
@@ -317,3 +365,40 @@ pub fn notify_desktop<P: AsRef<str>>(message: P, timeout: Option<Duration>) {
 pub fn display_time(display_time: bool) {
     TERMINAL.lock().unwrap().display_time(display_time);
 }
+
+/// Never read from the terminal, answering no to every prompt.
+pub fn set_unattended(unattended: bool) {
+    TERMINAL.lock().unwrap().set_unattended(unattended);
+}
+
+/// Tells whether nobody is there to answer prompts, so commands should not read from stdin either.
+pub fn is_unattended() -> bool {
+    TERMINAL.lock().unwrap().unattended
+}
+
+/// Run `f`, buffering everything it prints through this module and the output of the commands
+/// it runs instead of writing it to the terminal. Used to run steps concurrently.
+pub fn capture_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    CAPTURED.with_borrow_mut(|captured| *captured = Some(String::new()));
+    let result = f();
+    let output = CAPTURED.with_borrow_mut(Option::take).unwrap_or_default();
+    (result, output)
+}
+
+/// Tells whether the output of the current thread is being captured by `capture_output`.
+pub fn is_capturing() -> bool {
+    CAPTURED.with_borrow(Option::is_some)
+}
+
+/// Append `output` to the captured output of the current thread.
+///
+/// Returns `false` if the output is not being captured and should be printed instead.
+pub fn write_captured(output: &str) -> bool {
+    CAPTURED.with_borrow_mut(|captured| match captured {
+        Some(buffer) => {
+            buffer.push_str(output);
+            true
+        }
+        None => false,
+    })
+}
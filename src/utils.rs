@@ -1,11 +1,15 @@
 use std::env;
 use std::ffi::OsStr;
 use std::fmt::Debug;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::Duration;
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Context, Result};
+use etcetera::base_strategy::BaseStrategy;
 use rust_i18n::t;
+use serde::{de, Deserialize, Deserializer, Serializer};
 
 use tracing::{debug, error};
 use tracing_subscriber::layer::SubscriberExt;
@@ -15,8 +19,12 @@ use tracing_subscriber::{fmt, Registry};
 use tracing_subscriber::{registry, EnvFilter};
 
 use crate::command::CommandExt;
-use crate::config::DEFAULT_LOG_LEVEL;
+use crate::config::{LogRotation, DEFAULT_LOG_LEVEL};
 use crate::error::SkipStep;
+#[cfg(windows)]
+use crate::WINDOWS_DIRS;
+#[cfg(unix)]
+use crate::XDG_DIRS;
 
 pub trait PathExt
 where
@@ -144,11 +152,132 @@ pub fn require_option<T>(option: Option<T>, cause: String) -> Result<T> {
     }
 }
 
-pub fn string_prepend_str(string: &mut String, s: &str) {
-    let mut new_string = String::with_capacity(string.len() + s.len());
-    new_string.push_str(s);
-    new_string.push_str(string);
-    *string = new_string;
+/// Format a duration for humans, e.g. `4.2s`, `14m 02s` or `1h 05m`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if secs < 60 * 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// Return platform's data directory.
+pub fn data_dir() -> PathBuf {
+    #[cfg(unix)]
+    return XDG_DIRS.data_dir();
+
+    #[cfg(windows)]
+    return WINDOWS_DIRS.data_dir();
+}
+
+/// Return platform's state directory, or its data directory if it has no state directory.
+pub fn state_dir() -> PathBuf {
+    #[cfg(unix)]
+    return XDG_DIRS.state_dir().unwrap_or_else(data_dir);
+
+    #[cfg(windows)]
+    return WINDOWS_DIRS.state_dir().unwrap_or_else(data_dir);
+}
+
+/// Remove the files with `extension` in `dir`, which are named after the IDs of past runs, that
+/// `rotation` does not keep. `current` is the file of the current run, which is always kept, as are
+/// the files `pinned` returns true for.
+pub fn rotate_files(
+    dir: &Path,
+    extension: &str,
+    current: &Path,
+    rotation: LogRotation,
+    pinned: impl Fn(&Path) -> bool,
+) -> Result<()> {
+    let mut files: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension) && path != current)
+        .filter_map(|path| Some((fs::metadata(&path).ok()?, path)))
+        .collect();
+    // Run IDs sort like their start times, newest first.
+    files.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    let mut size = 0;
+    for (kept, (metadata, path)) in files.into_iter().enumerate() {
+        size += metadata.len();
+        let too_many = kept + 1 >= rotation.keep();
+        let too_old = rotation
+            .max_age()
+            .zip(metadata.modified().ok().and_then(|time| time.elapsed().ok()))
+            .is_some_and(|(max_age, age)| age > max_age);
+        let too_big = rotation.max_size().is_some_and(|max_size| size > max_size);
+
+        if (too_many || too_old || too_big) && !pinned(&path) {
+            debug!("Removing {}", path.display());
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a duration such as `30s`, `10m`, `1h30m` or `7d`. A number without unit is in seconds.
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || t!("Invalid duration: {duration}", duration = duration).to_string();
+    let duration = duration.trim();
+    if let Ok(seconds) = duration.parse() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    if duration.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = 0;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let seconds = match rest[..unit].trim() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        total += value * seconds;
+        rest = &rest[unit..];
+    }
+
+    Ok(Duration::from_secs(total))
+}
+
+/// Deserialize an optional duration written as described in `parse_duration`.
+pub fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|duration| parse_duration(&duration).map_err(de::Error::custom))
+        .transpose()
+}
+
+/// Serialize an optional duration in the format read by `parse_duration`, e.g. `1h30m`.
+pub fn serialize_duration<S: Serializer>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error> {
+    let Some(duration) = duration else {
+        return serializer.serialize_none();
+    };
+
+    let mut seconds = duration.as_secs();
+    let mut formatted = String::new();
+    for (unit, length) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)] {
+        if seconds >= length {
+            formatted.push_str(&format!("{}{unit}", seconds / length));
+            seconds %= length;
+        }
+    }
+    if seconds > 0 || formatted.is_empty() {
+        formatted.push_str(&format!("{seconds}s"));
+    }
+
+    serializer.serialize_str(&formatted)
 }
 
 #[cfg(target_family = "unix")]
@@ -170,6 +299,9 @@ pub fn hostname() -> Result<String> {
 }
 
 pub mod merge_strategies {
+    use std::hash::Hash;
+
+    use indexmap::IndexMap;
     use merge::Merge;
 
     use crate::config::Commands;
@@ -211,6 +343,26 @@ pub mod merge_strategies {
         }
     }
 
+    /// Merges two maps, merging the values of the keys present in both
+    pub fn map_merge_opt<K, V>(left: &mut Option<IndexMap<K, V>>, right: Option<IndexMap<K, V>>)
+    where
+        K: Hash + Eq,
+        V: Merge,
+    {
+        if let Some(ref mut left_inner) = left {
+            for (key, value) in right.into_iter().flatten() {
+                match left_inner.get_mut(&key) {
+                    Some(left_value) => left_value.merge(value),
+                    None => {
+                        left_inner.insert(key, value);
+                    }
+                }
+            }
+        } else {
+            *left = right;
+        }
+    }
+
     pub fn commands_merge_opt(left: &mut Option<Commands>, right: Option<Commands>) {
         if let Some(ref mut left_inner) = left {
             if let Some(right_inner) = right {